@@ -2,7 +2,7 @@ import React, { Fragment, useEffect, useState, useRef } from 'react';
 import './form.css';
 import './flex.css';
 import './games.css';
-import { checkError, GAME_INDEX, postArgs, rejectedPromiseHandler, SessionInfo, GET_GAME, GAME_NEW, GAME_JOIN, GAME_LEAVE, GAME_START } from './api';
+import { checkError, GAME_INDEX, postArgs, rejectedPromiseHandler, SessionInfo, GET_GAME, GAME_NEW, GAME_JOIN, GAME_LEAVE, GAME_START, GAME_EVENTS } from './api';
 import Gomoku from './Gomoku';
 import { Link, useParams } from 'react-router-dom';
 
@@ -146,22 +146,46 @@ export function Game(props: GameProps) {
     })
   }
 
+  // the single game page streams the game's events instead of polling it
+  const streamEvents = props.show_expand === false;
+
+  function streamGame(id: number): EventSource {
+    const events = new EventSource(GAME_EVENTS(id));
+    for(const name of ["state", "join", "leave", "start", "move", "finish"]) {
+      events.addEventListener(name, (e) => {
+        const game = JSON.parse((e as MessageEvent).data).game;
+        setGame(game);
+        // the server ends the stream once the game is over, so don't reconnect
+        if(game.status === "finished") {
+          events.close();
+        }
+      });
+    }
+    return events;
+  }
+
   function game_action(route: (path: number) => string) {
     fetch(route(props.id), {
       method: 'POST',
       credentials: 'include',
     }).then(resp => resp.json()).then(json => {
-      if(checkError(json)) {
+      if(checkError(json) && !streamEvents) {
         loadGame(props.id);
       }
     }).catch(rejectedPromiseHandler);
   }
 
   useEffect(() => { 
-    loadGame(props.id);
+    const events = streamEvents ? streamGame(props.id) : null;
+    if(!streamEvents) {
+      loadGame(props.id);
+    }
     
     return () => {
       mountedRef.current = false;
+      if(events !== null) {
+        events.close();
+      }
       if(shedId !== -1) {
         window.clearTimeout(shedId);
       }
@@ -24,6 +24,9 @@ export function GAME_START(id: number) {
 export function GAME_MOVE(id: number) {
   return `${API_ROUTE}/game/${id}/move`;
 }
+export function GAME_EVENTS(id: number) {
+  return `${API_ROUTE}/game/${id}/events`;
+}
 export function PAGE_GET(path: string) {
   return `${API_ROUTE}/pages/${path}`;
 }
@@ -0,0 +1 @@
+ALTER TABLE db_games DROP COLUMN game_type
\ No newline at end of file
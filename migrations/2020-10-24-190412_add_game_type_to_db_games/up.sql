@@ -0,0 +1 @@
+ALTER TABLE db_games ADD COLUMN game_type VARCHAR NOT NULL DEFAULT 'gomoku'
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE tournaments DROP COLUMN game_type
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE tournaments ADD COLUMN game_type VARCHAR NOT NULL DEFAULT 'gomoku'
\ No newline at end of file
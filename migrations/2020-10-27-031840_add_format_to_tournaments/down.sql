@@ -0,0 +1,5 @@
+ALTER TABLE tournaments DROP COLUMN format;
+ALTER TABLE tournaments DROP COLUMN current_round;
+ALTER TABLE tournaments DROP COLUMN rounds;
+ALTER TABLE tournaments DROP COLUMN byes;
+ALTER TABLE db_games DROP COLUMN tournament_id;
\ No newline at end of file
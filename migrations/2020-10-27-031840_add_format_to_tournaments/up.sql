@@ -0,0 +1,5 @@
+ALTER TABLE tournaments ADD COLUMN format VARCHAR NOT NULL DEFAULT 'round_robin';
+ALTER TABLE tournaments ADD COLUMN current_round INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tournaments ADD COLUMN rounds INTEGER;
+ALTER TABLE tournaments ADD COLUMN byes INTEGER[] NOT NULL DEFAULT '{}';
+ALTER TABLE db_games ADD COLUMN tournament_id INTEGER;
\ No newline at end of file
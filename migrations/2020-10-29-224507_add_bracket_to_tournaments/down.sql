@@ -0,0 +1,2 @@
+ALTER TABLE tournaments DROP COLUMN best_of;
+ALTER TABLE tournaments DROP COLUMN bracket;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE tournaments ADD COLUMN best_of INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE tournaments ADD COLUMN bracket TEXT;
\ No newline at end of file
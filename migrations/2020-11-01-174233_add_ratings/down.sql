@@ -0,0 +1,3 @@
+DROP TABLE rating_history;
+ALTER TABLE db_games DROP COLUMN rated;
+ALTER TABLE users DROP COLUMN rating;
\ No newline at end of file
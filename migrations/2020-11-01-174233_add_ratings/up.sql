@@ -0,0 +1,10 @@
+ALTER TABLE users ADD COLUMN rating DOUBLE PRECISION;
+ALTER TABLE db_games ADD COLUMN rated BOOLEAN NOT NULL DEFAULT false;
+CREATE TABLE rating_history (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    game_id INTEGER NOT NULL REFERENCES db_games(id),
+    rating_before DOUBLE PRECISION NOT NULL,
+    rating_after DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+)
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE users DROP COLUMN glicko_volatility;
+ALTER TABLE users DROP COLUMN glicko_deviation;
+ALTER TABLE users DROP COLUMN glicko_rating;
\ No newline at end of file
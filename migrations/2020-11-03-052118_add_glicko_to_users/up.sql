@@ -0,0 +1,3 @@
+ALTER TABLE users ADD COLUMN glicko_rating DOUBLE PRECISION;
+ALTER TABLE users ADD COLUMN glicko_deviation DOUBLE PRECISION;
+ALTER TABLE users ADD COLUMN glicko_volatility DOUBLE PRECISION;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE db_games DROP COLUMN moves;
+DROP TABLE game_players;
\ No newline at end of file
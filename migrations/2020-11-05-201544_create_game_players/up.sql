@@ -0,0 +1,18 @@
+CREATE TABLE game_players (
+    game_id INTEGER NOT NULL REFERENCES db_games(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    seat INTEGER NOT NULL,
+    PRIMARY KEY (game_id, seat)
+);
+CREATE INDEX game_players_user_id_idx ON game_players (user_id);
+INSERT INTO game_players (game_id, user_id, seat)
+    SELECT db_games.id, seats.user_id::INTEGER, seats.seat - 1
+    FROM db_games, json_array_elements_text(db_games.players::json) WITH ORDINALITY AS seats(user_id, seat);
+
+ALTER TABLE db_games ADD COLUMN moves INTEGER NOT NULL DEFAULT 0;
+-- gomoku is the only kind of game that existed before moves were counted: count the stones on the board
+UPDATE db_games SET moves = (
+    SELECT count(*)
+    FROM json_array_elements(db_games.state::json->'board') AS rows(cells), json_array_elements_text(rows.cells) AS cells(cell)
+    WHERE cells.cell <> '-1'
+) WHERE game_type = 'gomoku' AND state IS NOT NULL;
@@ -0,0 +1,7 @@
+ALTER TABLE db_games ADD COLUMN players VARCHAR NOT NULL DEFAULT '[]';
+UPDATE db_games SET players = (
+    SELECT COALESCE(json_agg(game_players.user_id ORDER BY game_players.seat), '[]')::VARCHAR
+    FROM game_players
+    WHERE game_players.game_id = db_games.id
+);
+ALTER TABLE db_games ALTER COLUMN players DROP DEFAULT;
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE db_games DROP COLUMN players;
\ No newline at end of file
@@ -0,0 +1,10 @@
+ALTER TABLE db_games DROP COLUMN last_move_at;
+ALTER TABLE db_games DROP COLUMN finished_at;
+ALTER TABLE db_games DROP COLUMN started_at;
+ALTER TABLE db_games DROP COLUMN created_at;
+ALTER TABLE db_games ADD COLUMN active INTEGER NOT NULL DEFAULT 0;
+UPDATE db_games SET active = CASE WHEN status IN ('lobby', 'running') THEN 1 ELSE 0 END;
+ALTER TABLE db_games ALTER COLUMN active DROP DEFAULT;
+DROP INDEX db_games_status_idx;
+ALTER TABLE db_games DROP COLUMN status;
+ALTER TABLE db_games ALTER COLUMN state TYPE TEXT USING state::TEXT;
\ No newline at end of file
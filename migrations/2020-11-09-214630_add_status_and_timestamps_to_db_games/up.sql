@@ -0,0 +1,17 @@
+ALTER TABLE db_games ALTER COLUMN state TYPE JSONB USING state::JSONB;
+ALTER TABLE db_games ADD COLUMN status VARCHAR NOT NULL DEFAULT 'lobby';
+UPDATE db_games SET status = CASE
+    WHEN state IS NULL THEN 'lobby'
+    WHEN active = 1 THEN 'running'
+    ELSE 'finished'
+END;
+ALTER TABLE db_games DROP COLUMN active;
+CREATE INDEX db_games_status_idx ON db_games (status);
+ALTER TABLE db_games ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE db_games ADD COLUMN started_at TIMESTAMP;
+ALTER TABLE db_games ADD COLUMN finished_at TIMESTAMP;
+ALTER TABLE db_games ADD COLUMN last_move_at TIMESTAMP;
+-- games from before timestamps were recorded are given the time of the migration
+UPDATE db_games SET started_at = NOW() WHERE status <> 'lobby';
+UPDATE db_games SET finished_at = NOW() WHERE status = 'finished';
+UPDATE db_games SET last_move_at = NOW() WHERE moves > 0;
\ No newline at end of file
@@ -0,0 +1,9 @@
+CREATE TABLE game_moves (
+    game_id INTEGER NOT NULL REFERENCES db_games(id) ON DELETE CASCADE,
+    ply INTEGER NOT NULL,
+    seat INTEGER NOT NULL,
+    player_move TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    think_time_ms BIGINT,
+    PRIMARY KEY (game_id, ply)
+)
\ No newline at end of file
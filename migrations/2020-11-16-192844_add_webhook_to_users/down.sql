@@ -0,0 +1 @@
+ALTER TABLE users DROP COLUMN webhook_url, DROP COLUMN webhook_secret;
\ No newline at end of file
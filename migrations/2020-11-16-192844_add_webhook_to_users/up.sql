@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN webhook_url TEXT, ADD COLUMN webhook_secret TEXT;
\ No newline at end of file
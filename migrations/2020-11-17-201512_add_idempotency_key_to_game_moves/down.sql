@@ -0,0 +1,2 @@
+DROP INDEX game_moves_idempotency_key;
+ALTER TABLE game_moves DROP COLUMN idempotency_key;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE game_moves ADD COLUMN idempotency_key TEXT;
+CREATE UNIQUE INDEX game_moves_idempotency_key ON game_moves (game_id, seat, idempotency_key);
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- outcomes of games that finished before this are recorded when the leaderboard is next loaded
+ALTER TABLE game_players ADD COLUMN outcome VARCHAR;
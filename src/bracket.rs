@@ -0,0 +1,431 @@
+use serde::{Deserialize, Serialize};
+
+/// Where a player in a match comes from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// the player with the given seed (1 is the top seed)
+    Seed(usize),
+    /// the winner of the match with the given index
+    WinnerOf(usize),
+    /// the loser of the match with the given index
+    LoserOf(usize),
+}
+
+/// A player position in a match
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Slot {
+    /// not known yet
+    Pending,
+    Player(i32),
+    /// nobody (a bye)
+    Empty,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Winners,
+    Losers,
+    Final,
+}
+
+/// A series of games between two players in a bracket
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Match {
+    pub side: Side,
+    /// round within the side, starting at 1
+    pub round: usize,
+    pub sources: [Source; 2],
+    pub slots: [Slot; 2],
+    /// games played in the series, in order
+    pub games: Vec<i32>,
+    /// number of games won by each slot
+    pub wins: [u32; 2],
+    pub winner: Slot,
+    pub loser: Slot,
+    /// if this is the grand final reset, which is only played if the losers bracket champion won the grand final
+    pub reset: bool,
+}
+
+/// The result of a game in a series
+#[derive(Clone, Copy, Debug)]
+pub enum SeriesGame {
+    /// still being played
+    Pending,
+    Won(i32),
+    Tied,
+}
+
+/// A single or double elimination bracket
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Bracket {
+    /// players in seed order
+    pub seeds: Vec<i32>,
+    /// number of games in each series (a player needs a majority of them to win)
+    pub best_of: u32,
+    /// matches in the bracket. Matches only take players from matches before them
+    pub matches: Vec<Match>,
+}
+
+/// order of seeds in the first round of a bracket with the given (power of two) size,
+/// so that the top seeds meet as late as possible
+fn seed_order(size: usize) -> Vec<usize> {
+    let mut order = vec![1];
+    while order.len() < size {
+        let len = order.len() * 2;
+        order = order.iter().flat_map(|s| vec![*s, len + 1 - s]).collect();
+    }
+
+    order
+}
+
+impl Match {
+    fn new(side: Side, round: usize, sources: [Source; 2]) -> Match {
+        Match {
+            side,
+            round,
+            sources,
+            slots: [Slot::Pending, Slot::Pending],
+            games: vec![],
+            wins: [0, 0],
+            winner: Slot::Pending,
+            loser: Slot::Pending,
+            reset: false,
+        }
+    }
+
+    fn decided(&self) -> bool {
+        self.winner != Slot::Pending
+    }
+
+    fn decide(&mut self, winner: usize) {
+        self.winner = self.slots[winner];
+        self.loser = self.slots[1 - winner];
+    }
+}
+
+impl Bracket {
+    /// create a single elimination bracket for the players (given in seed order)
+    pub fn single_elimination(seeds: Vec<i32>, best_of: u32) -> Bracket {
+        let mut bracket = Bracket {
+            seeds,
+            best_of,
+            matches: vec![],
+        };
+        bracket.add_winners_bracket();
+
+        bracket
+    }
+
+    /// create a double elimination bracket for the players (given in seed order)
+    pub fn double_elimination(seeds: Vec<i32>, best_of: u32) -> Bracket {
+        let mut bracket = Bracket {
+            seeds,
+            best_of,
+            matches: vec![],
+        };
+        let winners = bracket.add_winners_bracket();
+        let winners_final = winners.last().unwrap()[0];
+
+        let mut losers_champion = Source::LoserOf(winners_final);
+        if winners.len() > 1 {
+            // losers of the first winners round play each other
+            let first_round = winners[0]
+                .chunks(2)
+                .map(|pair| [Source::LoserOf(pair[0]), Source::LoserOf(pair[1])])
+                .collect();
+            let mut round = 1;
+            let mut losers = bracket.add_round(Side::Losers, round, first_round);
+
+            for r in 1..winners.len() {
+                // losers of each following winners round drop in to play the survivors
+                // (in reverse order, to avoid quick rematches)
+                let drop_in = losers
+                    .iter()
+                    .zip(winners[r].iter().rev())
+                    .map(|(survivor, dropped)| {
+                        [Source::WinnerOf(*survivor), Source::LoserOf(*dropped)]
+                    })
+                    .collect();
+                round += 1;
+                losers = bracket.add_round(Side::Losers, round, drop_in);
+
+                // then the survivors play each other
+                if r + 1 < winners.len() {
+                    let next = losers
+                        .chunks(2)
+                        .map(|pair| [Source::WinnerOf(pair[0]), Source::WinnerOf(pair[1])])
+                        .collect();
+                    round += 1;
+                    losers = bracket.add_round(Side::Losers, round, next);
+                }
+            }
+
+            losers_champion = Source::WinnerOf(losers[0]);
+        }
+
+        let grand_final = bracket.add_round(
+            Side::Final,
+            1,
+            vec![[Source::WinnerOf(winners_final), losers_champion]],
+        )[0];
+        let reset = bracket.add_round(
+            Side::Final,
+            2,
+            vec![[Source::WinnerOf(grand_final), Source::LoserOf(grand_final)]],
+        )[0];
+        bracket.matches[reset].reset = true;
+
+        bracket
+    }
+
+    /// add matches with the given sources, and return their indices
+    fn add_round(&mut self, side: Side, round: usize, sources: Vec<[Source; 2]>) -> Vec<usize> {
+        sources
+            .into_iter()
+            .map(|sources| {
+                self.matches.push(Match::new(side, round, sources));
+                self.matches.len() - 1
+            })
+            .collect()
+    }
+
+    /// add the winners bracket, and return the indices of the matches in each round
+    fn add_winners_bracket(&mut self) -> Vec<Vec<usize>> {
+        let mut size = 2;
+        while size < self.seeds.len() {
+            size *= 2;
+        }
+
+        let first_round = seed_order(size)
+            .chunks(2)
+            .map(|pair| [Source::Seed(pair[0]), Source::Seed(pair[1])])
+            .collect();
+        let mut rounds = vec![self.add_round(Side::Winners, 1, first_round)];
+        while rounds.last().unwrap().len() > 1 {
+            let next = rounds
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| [Source::WinnerOf(pair[0]), Source::WinnerOf(pair[1])])
+                .collect();
+            let round = rounds.len() + 1;
+            rounds.push(self.add_round(Side::Winners, round, next));
+        }
+
+        rounds
+    }
+
+    fn resolve(&self, source: Source) -> Slot {
+        match source {
+            Source::Seed(seed) => self
+                .seeds
+                .get(seed - 1)
+                .map_or(Slot::Empty, |id| Slot::Player(*id)),
+            Source::WinnerOf(m) => self.matches[m].winner,
+            Source::LoserOf(m) => self.matches[m].loser,
+        }
+    }
+
+    fn seed_of(&self, slot: Slot) -> usize {
+        match slot {
+            Slot::Player(id) => self
+                .seeds
+                .iter()
+                .position(|s| *s == id)
+                .unwrap_or(self.seeds.len()),
+            _ => self.seeds.len(),
+        }
+    }
+
+    /// Update the bracket from the results of its games, advancing the winners of finished series.
+    /// Returns the games that need to be started, as the index of their match and the players in seat order.
+    ///
+    /// Tied games don't count towards winning a series, and the series continues. If a series reaches twice
+    /// its length without a winner, the higher seed advances.
+    pub fn advance(&mut self, game_result: &dyn Fn(i32) -> SeriesGame) -> Vec<(usize, [i32; 2])> {
+        let needed = self.best_of / 2 + 1;
+        let max_games = (self.best_of * 2) as usize;
+        let mut to_start = vec![];
+
+        for i in 0..self.matches.len() {
+            if self.matches[i].decided() {
+                continue;
+            }
+            let slots = [
+                self.resolve(self.matches[i].sources[0]),
+                self.resolve(self.matches[i].sources[1]),
+            ];
+            let higher_seed = if self.seed_of(slots[1]) < self.seed_of(slots[0]) {
+                1
+            } else {
+                0
+            };
+            let reset_skipped = match (self.matches[i].reset, self.matches[i].sources[0]) {
+                (true, Source::WinnerOf(grand_final)) => {
+                    let grand_final = &self.matches[grand_final];
+                    grand_final.decided() && grand_final.winner == grand_final.slots[0]
+                }
+                _ => false,
+            };
+
+            let m = &mut self.matches[i];
+            m.slots = slots;
+            match slots {
+                [Slot::Pending, _] | [_, Slot::Pending] => continue,
+                [Slot::Empty, _] => m.decide(1),
+                [_, Slot::Empty] => m.decide(0),
+                [Slot::Player(first), Slot::Player(second)] => {
+                    // the grand final reset is only played if the winners bracket champion lost the grand final
+                    if reset_skipped {
+                        m.decide(0);
+                        continue;
+                    }
+
+                    let mut wins = [0, 0];
+                    let mut pending = false;
+                    for game in &m.games {
+                        match game_result(*game) {
+                            SeriesGame::Pending => pending = true,
+                            SeriesGame::Won(id) if id == first => wins[0] += 1,
+                            SeriesGame::Won(id) if id == second => wins[1] += 1,
+                            _ => (),
+                        }
+                    }
+                    m.wins = wins;
+
+                    if wins[0] >= needed {
+                        m.decide(0);
+                    } else if wins[1] >= needed {
+                        m.decide(1);
+                    } else if pending {
+                        continue;
+                    } else if m.games.len() >= max_games {
+                        m.decide(higher_seed);
+                    } else if m.games.len() % 2 == 0 {
+                        // alternate who moves first in each game of the series
+                        to_start.push((i, [first, second]));
+                    } else {
+                        to_start.push((i, [second, first]));
+                    }
+                }
+            }
+        }
+
+        to_start
+    }
+
+    /// record that a game was started for a match
+    pub fn add_game(&mut self, match_index: usize, game_id: i32) {
+        self.matches[match_index].games.push(game_id);
+    }
+
+    /// get the winner of the bracket, if it is finished
+    pub fn champion(&self) -> Option<i32> {
+        match self.matches.last().map(|m| m.winner) {
+            Some(Slot::Player(id)) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// play out the bracket, deciding each game from its match index and players in seat order
+    fn play(bracket: &mut Bracket, result: &dyn Fn(usize, [i32; 2]) -> SeriesGame) {
+        let mut results = HashMap::new();
+        loop {
+            let to_start = bracket.advance(&|game| results[&game]);
+            if to_start.is_empty() {
+                return;
+            }
+            for (m, players) in to_start {
+                let game = results.len() as i32;
+                results.insert(game, result(m, players));
+                bracket.add_game(m, game);
+            }
+        }
+    }
+
+    /// the player with the better (lower) seed, which is also their id in these tests
+    fn higher_seed(players: [i32; 2]) -> SeriesGame {
+        SeriesGame::Won(players[0].min(players[1]))
+    }
+
+    #[test]
+    fn top_seeds_meet_as_late_as_possible() {
+        assert_eq!(seed_order(8), vec![1, 8, 4, 5, 2, 7, 3, 6]);
+    }
+
+    #[test]
+    fn single_elimination_gives_byes_to_top_seeds() {
+        let mut bracket = Bracket::single_elimination(vec![1, 2, 3], 1);
+        play(&mut bracket, &|_, players| higher_seed(players));
+
+        assert_eq!(bracket.champion(), Some(1));
+        // seed 1 has a bye in the first round
+        assert_eq!(bracket.matches[0].slots, [Slot::Player(1), Slot::Empty]);
+        assert!(bracket.matches[0].games.is_empty());
+        let games = bracket.matches.iter().map(|m| m.games.len()).sum::<usize>();
+        assert_eq!(games, 2);
+    }
+
+    #[test]
+    fn series_ignores_ties_and_falls_back_to_higher_seed() {
+        let mut bracket = Bracket::single_elimination(vec![1, 2], 3);
+        play(&mut bracket, &|_, players| {
+            if players[0] == 2 {
+                SeriesGame::Won(2)
+            } else {
+                SeriesGame::Tied
+            }
+        });
+        // seats alternate, so seed 2 only wins the second and fourth games
+        assert_eq!(bracket.matches[0].games.len(), 4);
+        assert_eq!(bracket.matches[0].wins, [0, 2]);
+        assert_eq!(bracket.champion(), Some(2));
+
+        let mut bracket = Bracket::single_elimination(vec![1, 2], 1);
+        play(&mut bracket, &|_, _| SeriesGame::Tied);
+        assert_eq!(bracket.matches[0].games.len(), 2);
+        assert_eq!(bracket.champion(), Some(1));
+    }
+
+    #[test]
+    fn double_elimination_skips_reset_when_winners_champion_wins_grand_final() {
+        let mut bracket = Bracket::double_elimination(vec![1, 2, 3, 4], 1);
+        play(&mut bracket, &|_, players| higher_seed(players));
+
+        let grand_final = &bracket.matches[bracket.matches.len() - 2];
+        assert_eq!(grand_final.side, Side::Final);
+        assert_eq!(grand_final.slots, [Slot::Player(1), Slot::Player(2)]);
+        let reset = bracket.matches.last().unwrap();
+        assert!(reset.reset);
+        assert!(reset.games.is_empty());
+        assert_eq!(bracket.champion(), Some(1));
+    }
+
+    #[test]
+    fn double_elimination_plays_reset_when_losers_champion_wins_grand_final() {
+        let mut bracket = Bracket::double_elimination(vec![1, 2, 3, 4], 1);
+        let grand_final = bracket.matches.len() - 2;
+        play(&mut bracket, &|m, players| {
+            if m == grand_final {
+                SeriesGame::Won(2)
+            } else {
+                higher_seed(players)
+            }
+        });
+
+        assert_eq!(bracket.matches[grand_final].winner, Slot::Player(2));
+        let reset = bracket.matches.last().unwrap();
+        assert_eq!(reset.slots, [Slot::Player(2), Slot::Player(1)]);
+        assert_eq!(reset.games.len(), 1);
+        assert_eq!(bracket.champion(), Some(1));
+    }
+}
@@ -0,0 +1,115 @@
+use std::cmp;
+use std::io::{self, Read};
+
+/// size of hyper's response buffer, which is only written to the client once it is full
+const RESPONSE_BUFFER_SIZE: usize = 8192;
+
+/// Reads messages as a streamed response body, so that each message is sent to the client as soon as it is read.
+///
+/// Rocket 0.4 has no way to flush a streamed response. It reads each chunk of the body until its buffer is full
+/// or a read returns 0, and writes the chunk into hyper's 8KB buffer, which is only written out when it is full.
+/// To get each message sent right away:
+/// - a read returns 0 after the end of each message, so rocket writes the message as its own chunk
+///   (rocket only ends the body when a whole chunk is empty)
+/// - messages are padded with an event stream comment to the size of hyper's buffer, so writing them fills it
+///
+/// This can be replaced by flushing once rocket supports it.
+pub struct MessageReader<M> {
+    messages: M,
+    /// the message being read, and how much of it has been read
+    message: Vec<u8>,
+    read: usize,
+    /// if the message ended partway through a read, so the next read should end the chunk it is sent in
+    end_chunk: bool,
+}
+
+impl<M: Iterator<Item = String>> MessageReader<M> {
+    pub fn new(messages: M) -> MessageReader<M> {
+        MessageReader {
+            messages,
+            message: vec![],
+            read: 0,
+            end_chunk: false,
+        }
+    }
+}
+
+/// pad a message in an event stream with a comment, so it fills hyper's buffer
+fn pad_message(message: String) -> String {
+    let padding = RESPONSE_BUFFER_SIZE.saturating_sub(message.len() + 2);
+    format!(":{}\n{}", " ".repeat(padding), message)
+}
+
+impl<M: Iterator<Item = String>> Read for MessageReader<M> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.message.len() {
+            if self.end_chunk {
+                self.end_chunk = false;
+                return Ok(0);
+            }
+            self.message = match self.messages.next() {
+                Some(message) => pad_message(message).into_bytes(),
+                None => return Ok(0),
+            };
+            self.read = 0;
+        }
+
+        let len = cmp::min(buf.len(), self.message.len() - self.read);
+        buf[..len].copy_from_slice(&self.message[self.read..self.read + len]);
+        self.read += len;
+        self.end_chunk = self.read == self.message.len() && len < buf.len();
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// read the chunks of a body the way rocket does, filling a buffer of the given size until a read returns 0
+    fn read_chunks(body: &mut dyn Read, size: usize) -> Vec<String> {
+        let mut chunks = vec![];
+        loop {
+            let mut buf = vec![0; size];
+            let mut len = 0;
+            while len < size {
+                match body.read(&mut buf[len..]).unwrap() {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+            if len == 0 {
+                return chunks;
+            }
+            chunks.push(String::from_utf8(buf[..len].to_vec()).unwrap());
+        }
+    }
+
+    #[test]
+    fn each_message_is_a_padded_chunk() {
+        let messages = vec!["event: a\n\n".to_string(), "event: b\n\n".to_string()];
+        let chunks = read_chunks(&mut MessageReader::new(messages.into_iter()), 65536);
+
+        assert_eq!(chunks.len(), 2);
+        for (chunk, name) in chunks.iter().zip(&["a", "b"]) {
+            assert_eq!(chunk.len(), RESPONSE_BUFFER_SIZE);
+            assert!(chunk.starts_with(": "));
+            assert!(chunk.ends_with(&format!("\nevent: {}\n\n", name)));
+        }
+    }
+
+    #[test]
+    fn long_messages_span_chunks_and_end_their_last_one() {
+        let long = format!("data: {}\n\n", "x".repeat(10000));
+        let messages = vec![long.clone(), "event: next\n\n".to_string()];
+        let chunks = read_chunks(&mut MessageReader::new(messages.into_iter()), 4096);
+
+        // the long message isn't padded, and is split into full chunks until it ends. the next message fills two chunks
+        let lens = chunks
+            .iter()
+            .map(|chunk| chunk.len())
+            .collect::<Vec<usize>>();
+        assert_eq!(lens, vec![4096, 4096, 1818, 4096, 4096]);
+        assert_eq!(chunks[..3].concat(), format!(":\n{}", long));
+    }
+}
@@ -1,11 +1,13 @@
-use core::fmt::Display;
-use rocket::request::FromForm;
+use crate::shared::Error;
+use core::fmt::{Debug, Display, Formatter};
+use rocket::request::{FormItems, FromForm};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::ops::Add;
 
 pub type GamePlayer = u32;
 
+#[derive(PartialEq, Debug)]
 pub enum GameOutcome {
     Win(GamePlayer),
     Tie,
@@ -16,7 +18,7 @@ pub enum GameOutcome {
 /// Some type of game. It is expected to be turn based, and eventually reach an end state.
 pub trait Game: Clone {
     type Move: for<'f> FromForm<'f>;
-    type Score: Add + Serialize + Display;
+    type Score: Add + Serialize + Display + Into<f64>;
     type State: Serialize + DeserializeOwned;
 
     /// Check if a game can be created with the number of players
@@ -39,3 +41,150 @@ pub trait Game: Clone {
     /// get the game outcome, or None if game doesn't have outcome yet
     fn outcome(&self) -> GameOutcome;
 }
+
+/// A type erased Game, so that games of different kinds can be stored and managed together.
+/// Every Game implements AnyGame.
+pub trait AnyGame: Send + Sync {
+    /// Get the state of the game serialized as json
+    fn state(&self, for_player: GamePlayer) -> Result<serde_json::Value, Error>;
+    /// Check if the game is finished
+    fn finished(&self) -> bool;
+    /// Check if the game is waiting on a move by the given player
+    fn waiting_on(&self, player: GamePlayer) -> bool;
+    /// Parse a move from a urlencoded form and make it for the given player.
+    /// Returns Ok(true) if the move was legal and made, and Ok(false) if it was illegal.
+    fn make_move(&mut self, player: GamePlayer, move_form: &str) -> Result<bool, Error>;
+    /// Get the score for each player. If scores are not available at the current point in the game, return None.
+    fn scores(&self) -> Option<Vec<f64>>;
+    /// get the game outcome, or None if game doesn't have outcome yet
+    fn outcome(&self) -> GameOutcome;
+    /// Clone the game into a new box
+    fn box_clone(&self) -> Box<dyn AnyGame>;
+}
+
+impl<G> AnyGame for G
+where
+    G: Game + Send + Sync + 'static,
+{
+    fn state(&self, for_player: GamePlayer) -> Result<serde_json::Value, Error> {
+        Ok(serde_json::to_value(Game::state(self, for_player))?)
+    }
+
+    fn finished(&self) -> bool {
+        Game::finished(self)
+    }
+
+    fn waiting_on(&self, player: GamePlayer) -> bool {
+        Game::waiting_on(self, player)
+    }
+
+    fn make_move(&mut self, player: GamePlayer, move_form: &str) -> Result<bool, Error> {
+        let mut items = FormItems::from(move_form);
+        let parsed = G::Move::from_form(&mut items, true);
+        match parsed {
+            Ok(move_to_make) if items.exhaust() => Ok(Game::make_move(self, player, &move_to_make)),
+            _ => Err(Error::InvalidMove),
+        }
+    }
+
+    fn scores(&self) -> Option<Vec<f64>> {
+        Game::scores(self).map(|scores| scores.into_iter().map(|s| s.into()).collect())
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        Game::outcome(self)
+    }
+
+    fn box_clone(&self) -> Box<dyn AnyGame> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn AnyGame> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+impl Debug for dyn AnyGame {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self.state(0) {
+            Ok(state) => write!(f, "AnyGame({})", state),
+            Err(_) => write!(f, "AnyGame(<unserializable>)"),
+        }
+    }
+}
+
+/// A kind of game that the server can host, identified by a string id
+pub struct GameKind {
+    pub id: &'static str,
+    check_num_players: fn(usize) -> bool,
+    new_with_players: fn(usize) -> Box<dyn AnyGame>,
+    from_state: fn(serde_json::Value, usize) -> Result<Box<dyn AnyGame>, serde_json::Error>,
+}
+
+impl GameKind {
+    /// create the kind for the Game type G
+    pub fn of<G: Game + Send + Sync + 'static>(id: &'static str) -> GameKind {
+        GameKind {
+            id,
+            check_num_players: G::check_num_players,
+            new_with_players: |players| Box::new(G::new_with_players(players)),
+            from_state: |state, players| {
+                Ok(Box::new(G::from_state(
+                    serde_json::from_value::<G::State>(state)?,
+                    players,
+                )))
+            },
+        }
+    }
+
+    /// Check if a game of this kind can be created with the number of players
+    pub fn check_num_players(&self, players: usize) -> bool {
+        (self.check_num_players)(players)
+    }
+
+    /// Create a game of this kind with the given number of players
+    pub fn new_with_players(&self, players: usize) -> Box<dyn AnyGame> {
+        (self.new_with_players)(players)
+    }
+
+    /// Create a game of this kind from a json state and number of players
+    pub fn from_state(
+        &self,
+        state: serde_json::Value,
+        players: usize,
+    ) -> Result<Box<dyn AnyGame>, Error> {
+        Ok((self.from_state)(state, players)?)
+    }
+}
+
+/// The kinds of games hosted by the server
+#[derive(Default)]
+pub struct GameRegistry {
+    kinds: Vec<GameKind>,
+}
+
+impl GameRegistry {
+    pub fn new() -> GameRegistry {
+        GameRegistry { kinds: vec![] }
+    }
+
+    /// add the Game type G to the registry under the given id
+    pub fn register<G: Game + Send + Sync + 'static>(&mut self, id: &'static str) {
+        self.kinds.push(GameKind::of::<G>(id));
+    }
+
+    /// find the kind of game with the given id
+    pub fn get(&self, id: &str) -> Result<&GameKind, Error> {
+        self.kinds
+            .iter()
+            .find(|kind| kind.id == id)
+            .ok_or(Error::InvalidGameType)
+    }
+
+    /// get the ids of all registered kinds of games
+    pub fn ids(&self) -> Vec<&'static str> {
+        self.kinds.iter().map(|kind| kind.id).collect()
+    }
+}
@@ -1,18 +1,49 @@
-use crate::game::{Game, GameOutcome, GamePlayer};
-use crate::models::{DbGame, InsertDbGame, NewDbGame, NewTournament, Tournament, User};
-use crate::shared::{DBConn, Error, ErrorResp, IdResp, SuccessResp};
-use crate::users::{ForwardingUser, PlayerId};
+use crate::bracket::{Bracket, SeriesGame};
+use crate::event_stream::MessageReader;
+use crate::game::{AnyGame, GameKind, GameOutcome, GamePlayer, GameRegistry};
+use crate::glicko::Glicko;
+use crate::leaderboard::{compute_leaderboard, seat_outcome, LeaderboardEntry, Record};
+use crate::models::{
+    DbGame, DbGameMove, DbGamePlayer, InsertDbGame, NewDbGame, NewDbGameMove, NewTournament,
+    Tournament, User,
+};
+use crate::ratings;
+use crate::shared::{
+    unix_time, ClientIp, DBConn, DBPool, Error, ErrorResp, IdResp, IfNoneMatch, LazyDBConn,
+    SuccessResp, Tagged,
+};
+use crate::standings::{compute_standings, GameResult, Standing};
+use crate::swiss::{self, SwissPlayer};
+use crate::users::{ForwardingUser, PlayerId, UserManager};
 use crate::TOURNAMENT_GAME_PLAYERS;
 use core::fmt::Debug;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use hmac::{Hmac, Mac, NewMac};
 use itertools::Itertools;
+use rocket::http::ContentType;
 use rocket::request::Form;
+use rocket::response::content::Content;
+use rocket::response::Stream;
 use rocket::State;
 use rocket_contrib::json::Json;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::convert::{From, TryFrom};
-use std::sync::{RwLock, RwLockWriteGuard};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::convert::From;
+use std::io;
+use std::iter;
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tungstenite::handshake::HandshakeError;
+use tungstenite::{Message, WebSocket};
+use url::Url;
 
 #[derive(PartialEq, Eq, Copy, Clone, Hash, Serialize, Deserialize, Default, Debug)]
 pub struct GameId(i32);
@@ -44,20 +75,107 @@ impl ToString for TournamentId {
     }
 }
 
+/// How the games of a tournament are scheduled
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum TournamentFormat {
+    /// every combination of players plays one game
+    RoundRobin,
+    /// rounds are paired one at a time from the current standings
+    Swiss,
+    /// seeded knockout bracket
+    SingleElimination,
+    /// seeded knockout bracket, where players are knocked out after losing twice
+    DoubleElimination,
+}
+
+impl TournamentFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TournamentFormat::RoundRobin => "round_robin",
+            TournamentFormat::Swiss => "swiss",
+            TournamentFormat::SingleElimination => "single_elimination",
+            TournamentFormat::DoubleElimination => "double_elimination",
+        }
+    }
+}
+
+impl FromStr for TournamentFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round_robin" => Ok(TournamentFormat::RoundRobin),
+            "swiss" => Ok(TournamentFormat::Swiss),
+            "single_elimination" => Ok(TournamentFormat::SingleElimination),
+            "double_elimination" => Ok(TournamentFormat::DoubleElimination),
+            _ => Err(Error::InvalidTournamentFormat),
+        }
+    }
+}
+
+/// Where a game is in its lifecycle
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum GameStatus {
+    /// waiting for players to join and the owner to start it
+    Lobby,
+    /// started and waiting on moves
+    Running,
+    /// played to the end
+    Finished,
+}
+
+impl GameStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameStatus::Lobby => "lobby",
+            GameStatus::Running => "running",
+            GameStatus::Finished => "finished",
+        }
+    }
+}
+
+impl FromStr for GameStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lobby" => Ok(GameStatus::Lobby),
+            "running" => Ok(GameStatus::Running),
+            "finished" => Ok(GameStatus::Finished),
+            _ => Err(Error::InvalidGameStatus),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
-struct GameInstance<G: Game> {
+pub struct GameInstance {
     /// If the game has not yet started, game is None
-    game: Option<Box<G>>,
+    game: Option<Box<dyn AnyGame>>,
+    /// Id of the kind of game (in the GameRegistry)
+    game_type: &'static str,
     /// Players currently in the game
     players: Vec<PlayerId>,
     name: String,
     owner: PlayerId,
     id: GameId,
+    /// Tournament the game is part of, if any
+    tournament: Option<TournamentId>,
+    /// If the game changes the ratings of its players
+    rated: bool,
+    /// Number of moves made in the game
+    moves: i32,
+    created_at: SystemTime,
+    started_at: Option<SystemTime>,
+    /// when the game finished
+    finished_at: Option<SystemTime>,
+    last_move_at: Option<SystemTime>,
+    /// Number of times the game has been saved, used to reject saves of a game that was changed after it was loaded
+    version: i32,
 
     is_public: bool,
 }
 
-impl<G: Game> GameInstance<G> {
+impl GameInstance {
     /// check if the game has been started
     fn started(&self) -> bool {
         match &self.game {
@@ -72,6 +190,35 @@ impl<G: Game> GameInstance<G> {
             Some(g) => !g.finished(),
         }
     }
+    /// check if the game is active and waiting on a move from the player in the given seat
+    fn waiting_on(&self, seat: GamePlayer) -> bool {
+        self.active()
+            && self
+                .game
+                .as_ref()
+                .map_or(false, |game| game.waiting_on(seat))
+    }
+    /// get where the game is in its lifecycle
+    fn status(&self) -> GameStatus {
+        match &self.game {
+            None => GameStatus::Lobby,
+            Some(g) if g.finished() => GameStatus::Finished,
+            Some(_) => GameStatus::Running,
+        }
+    }
+    /// set the game's timestamps for changes made since the previous saved copy of it
+    fn update_timestamps(&mut self, previous: Option<&GameInstance>) {
+        let now = SystemTime::now();
+        if self.started() && self.started_at.is_none() {
+            self.started_at = Some(now);
+        }
+        if self.moves > previous.map_or(self.moves, |prev| prev.moves) {
+            self.last_move_at = Some(now);
+        }
+        if self.started() && !self.active() && self.finished_at.is_none() {
+            self.finished_at = Some(now);
+        }
+    }
     /// get GamePlayer for a player id
     fn get_player_index(&self, player: PlayerId) -> Result<GamePlayer, Error> {
         Ok(self
@@ -80,595 +227,4245 @@ impl<G: Game> GameInstance<G> {
             .position(|id| *id == player)
             .map_or(Err(Error::NotJoinedGame), |index| Ok(index as u32))?)
     }
-}
+    /// get a description of the game's outcome, given the display names of its players
+    fn outcome_message(&self, player_names: &[String]) -> String {
+        self.game
+            .as_ref()
+            .map_or("No Outcome Yet".to_string(), |g| match g.outcome() {
+                GameOutcome::Win(player) => format!("{} Wins!", &player_names[player as usize]),
+                GameOutcome::Tie => "Game Tied!".to_string(),
+                GameOutcome::Other(msg) => msg,
+                GameOutcome::None => "No Outcome Yet".to_string(),
+            })
+    }
+    /// get the result of the game, if it is finished and has scores
+    pub fn result(&self) -> Option<GameResult> {
+        if self.status() != GameStatus::Finished {
+            return None;
+        }
+        let game = self.game.as_ref()?;
+        let scores = game.scores()?;
+        let (winner, tie) = match game.outcome() {
+            GameOutcome::Win(player) => (Some(player as usize), false),
+            GameOutcome::Tie => (None, true),
+            _ => (None, false),
+        };
 
-impl<G: Game> TryFrom<DbGame> for GameInstance<G> {
-    type Error = serde_json::Error;
-    fn try_from(entry: DbGame) -> Result<GameInstance<G>, Self::Error> {
-        let players = serde_json::from_str::<Vec<i32>>(&entry.players)?
-            .iter()
-            .map(|id| PlayerId::new(*id))
-            .collect::<Vec<PlayerId>>();
+        Some(GameResult {
+            players: self.players.iter().map(|id| id.id()).collect(),
+            scores,
+            winner,
+            tie,
+        })
+    }
+    /// load a game from a db entry and its players (in seat order). The game's kind is looked up in the registry
+    fn from_db(
+        entry: DbGame,
+        players: Vec<PlayerId>,
+        kinds: &GameRegistry,
+    ) -> Result<GameInstance, Error> {
+        let kind = kinds.get(&entry.game_type)?;
         let game = match entry.state {
-            Some(s) => Some(Box::new(G::from_state(
-                serde_json::from_str::<G::State>(&s)?,
-                players.len(),
-            ))),
+            Some(s) => Some(kind.from_state(s, players.len())?),
             None => None,
         };
         Ok(GameInstance {
             id: GameId(entry.id),
             game,
+            game_type: kind.id,
             players,
             name: entry.title,
             owner: PlayerId::new(entry.owner_id),
+            tournament: entry.tournament_id.map(TournamentId),
+            rated: entry.rated,
+            moves: entry.moves,
+            created_at: entry.created_at,
+            started_at: entry.started_at,
+            finished_at: entry.finished_at,
+            last_move_at: entry.last_move_at,
+            version: entry.version,
             is_public: entry.is_public,
         })
     }
 }
 
-impl<'a, G: Game> From<&'a GameInstance<G>> for InsertDbGame<'a> {
-    fn from(inst: &GameInstance<G>) -> InsertDbGame {
+/// load the players of the games with the given ids, in seat order
+fn load_game_players(
+    conn: &PgConnection,
+    ids: Vec<i32>,
+) -> Result<HashMap<i32, Vec<PlayerId>>, Error> {
+    use crate::schema::game_players;
+
+    let mut players = HashMap::<i32, Vec<PlayerId>>::new();
+    for seat in game_players::dsl::game_players
+        .filter(game_players::dsl::game_id.eq_any(ids))
+        .order((game_players::dsl::game_id, game_players::dsl::seat))
+        .load::<DbGamePlayer>(conn)?
+    {
+        players
+            .entry(seat.game_id)
+            .or_default()
+            .push(PlayerId::new(seat.user_id));
+    }
+
+    Ok(players)
+}
+
+/// load games from their db entries, along with their players. each entry gives its game, or the error loading it.
+/// running games are loaded as they were last written to the db, so they may be missing moves that are only in the cache
+/// and move log. use `AppState::get_game` (or `catch_up_from_log`) for their latest state
+pub fn games_from_db(
+    conn: &PgConnection,
+    entries: Vec<DbGame>,
+    kinds: &GameRegistry,
+) -> Result<Vec<Result<GameInstance, Error>>, Error> {
+    let ids = entries.iter().map(|entry| entry.id).collect::<Vec<i32>>();
+    let mut players = load_game_players(conn, ids)?;
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            let game_players = players.remove(&entry.id).unwrap_or_default();
+            GameInstance::from_db(entry, game_players, kinds)
+        })
+        .collect())
+}
+
+/// write a game to the database, unless the database already has the same or a newer version of it.
+/// returns if the game was written
+fn write_game(conn: &PgConnection, game: &GameInstance) -> Result<bool, Error> {
+    use crate::schema::db_games;
+    let new_entry = InsertDbGame::from(game);
+
+    conn.transaction::<_, Error, _>(|| {
+        let updated = diesel::update(
+            db_games::dsl::db_games
+                .find(game.id.id())
+                .filter(db_games::dsl::version.lt(game.version)),
+        )
+        .set(&new_entry)
+        .execute(conn)?;
+        // players can only join or leave before the game starts
+        if updated > 0 && !game.started() {
+            write_game_players(conn, game.id, &game.players)?;
+        }
+        if let (true, Some(result)) = (updated > 0, game.result()) {
+            write_outcomes(conn, game.id, &result)?;
+        }
+
+        Ok(updated > 0)
+    })
+}
+
+/// write the seats of a game's players to the database
+fn write_game_players(
+    conn: &PgConnection,
+    game_id: GameId,
+    players: &[PlayerId],
+) -> Result<(), Error> {
+    use crate::schema::game_players;
+
+    let seats = players
+        .iter()
+        .enumerate()
+        .map(|(seat, player)| DbGamePlayer {
+            game_id: game_id.id(),
+            user_id: player.id(),
+            seat: seat as i32,
+            outcome: None,
+        })
+        .collect::<Vec<DbGamePlayer>>();
+
+    diesel::delete(
+        game_players::dsl::game_players.filter(game_players::dsl::game_id.eq(game_id.id())),
+    )
+    .execute(conn)?;
+    diesel::insert_into(game_players::table)
+        .values(&seats)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// write how a finished game ended for each of its players, so records can be counted without loading games
+fn write_outcomes(conn: &PgConnection, game_id: GameId, result: &GameResult) -> Result<(), Error> {
+    use crate::schema::game_players;
+
+    for seat in 0..result.players.len() {
+        diesel::update(game_players::dsl::game_players.find((game_id.id(), seat as i32)))
+            .set(game_players::dsl::outcome.eq(seat_outcome(result, seat)))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+/// moves made in running games are only saved to the cache and the move log, so a game loaded from the database may be missing its latest moves.
+/// make any moves in the game's log that are newer than the game
+fn catch_up_from_log(conn: &PgConnection, game: &mut GameInstance) -> Result<(), Error> {
+    use crate::schema::game_moves;
+
+    if !game.active() {
+        return Ok(());
+    }
+    let newer_moves = game_moves::dsl::game_moves
+        .filter(game_moves::dsl::game_id.eq(game.id.id()))
+        .filter(game_moves::dsl::ply.gt(game.moves))
+        .order(game_moves::dsl::ply.asc())
+        .load::<DbGameMove>(conn)?;
+
+    for game_move in newer_moves {
+        let seat = game_move.seat as GamePlayer;
+        let g = game.game.as_mut().ok_or(Error::ReplayFailed)?;
+        if !g.waiting_on(seat) || !g.make_move(seat, &game_move.player_move)? {
+            return Err(Error::ReplayFailed);
+        }
+        game.moves = game_move.ply;
+        game.last_move_at = Some(game_move.created_at);
+        // each move was saved to the cache as a new version
+        game.version += 1;
+    }
+
+    Ok(())
+}
+
+/// create a game of the given kind and replay moves from its move log in it
+pub fn replay_moves(
+    kind: &GameKind,
+    num_players: usize,
+    moves: &[DbGameMove],
+) -> Result<Box<dyn AnyGame>, Error> {
+    let mut game = kind.new_with_players(num_players);
+    for game_move in moves {
+        let seat = game_move.seat as GamePlayer;
+        if !game.waiting_on(seat) || !game.make_move(seat, &game_move.player_move)? {
+            return Err(Error::ReplayFailed);
+        }
+    }
+
+    Ok(game)
+}
+
+/// number of games loaded at a time when checking games
+const CHECK_BATCH_SIZE: i64 = 500;
+
+/// check every game in the db against its move log by replaying the log and comparing the result with the stored game.
+/// games that can't be loaded or replayed are reported as divergences, instead of stopping the check.
+/// running games are read from the db, which may be behind the server's cache, so moves logged after the stored move count aren't compared.
+/// returns the number of games checked and a description of each divergence found (as game id, problem)
+pub fn check_games(
+    conn: &PgConnection,
+    kinds: &GameRegistry,
+) -> Result<(usize, Vec<(i32, String)>), Error> {
+    use crate::schema::db_games;
+    use crate::schema::game_moves;
+
+    let mut checked = 0;
+    let mut divergences = vec![];
+    let mut last_id = 0;
+    loop {
+        let entries = db_games::dsl::db_games
+            .filter(db_games::dsl::id.gt(last_id))
+            .order(db_games::dsl::id.asc())
+            .limit(CHECK_BATCH_SIZE)
+            .load::<DbGame>(conn)?;
+        let last_entry = match entries.last() {
+            Some(entry) => entry.id,
+            None => break,
+        };
+        let ids = entries.iter().map(|entry| entry.id).collect::<Vec<i32>>();
+        let mut players = load_game_players(conn, ids.clone())?;
+
+        let mut moves = HashMap::<i32, Vec<DbGameMove>>::new();
+        for game_move in game_moves::dsl::game_moves
+            .filter(game_moves::dsl::game_id.eq_any(ids))
+            .order((game_moves::dsl::game_id, game_moves::dsl::ply))
+            .load::<DbGameMove>(conn)?
+        {
+            moves.entry(game_move.game_id).or_default().push(game_move);
+        }
+
+        for entry in entries {
+            let id = entry.id;
+            let game_players = players.remove(&id).unwrap_or_default();
+            let game_moves = moves.remove(&id).unwrap_or_default();
+            let problems =
+                check_entry(entry, game_players, &game_moves, kinds).unwrap_or_else(|err| {
+                    vec![format!(
+                        "game can't be checked: {}",
+                        ErrorResp::from(err).error
+                    )]
+                });
+            for problem in problems {
+                divergences.push((id, problem));
+            }
+            checked += 1;
+        }
+        last_id = last_entry;
+    }
+
+    Ok((checked, divergences))
+}
+
+/// load a game from its db entry and players, and check it against its move log
+fn check_entry(
+    entry: DbGame,
+    players: Vec<PlayerId>,
+    moves: &[DbGameMove],
+    kinds: &GameRegistry,
+) -> Result<Vec<String>, Error> {
+    let status = entry.status.parse::<GameStatus>()?;
+    let game = GameInstance::from_db(entry, players, kinds)?;
+
+    check_game(&game, status, moves, kinds)
+}
+
+/// compare a game (and its stored status) with the result of replaying its move log, and describe any differences
+fn check_game(
+    game: &GameInstance,
+    status: GameStatus,
+    moves: &[DbGameMove],
+    kinds: &GameRegistry,
+) -> Result<Vec<String>, Error> {
+    let mut problems = vec![];
+    if status != game.status() {
+        problems.push(format!(
+            "stored status is {}, but stored state is {}",
+            status.as_str(),
+            game.status().as_str()
+        ));
+    }
+    let stored = match &game.game {
+        Some(stored) => stored,
+        None if moves.is_empty() && game.moves == 0 => return Ok(problems),
+        None => {
+            problems.push(format!(
+                "game hasn't started, but has {} moves ({} logged)",
+                game.moves,
+                moves.len()
+            ));
+            return Ok(problems);
+        }
+    };
+    // moves in running games are saved to the move log before the game, so the log may be ahead
+    let moves = if status == GameStatus::Running && moves.len() > game.moves as usize {
+        &moves[..game.moves as usize]
+    } else {
+        moves
+    };
+    if moves.len() != game.moves as usize {
+        problems.push(format!(
+            "game has {} moves, but {} are logged",
+            game.moves,
+            moves.len()
+        ));
+        return Ok(problems);
+    }
+    let replayed = match replay_moves(kinds.get(game.game_type)?, game.players.len(), moves) {
+        Ok(replayed) => replayed,
+        Err(Error::ReplayFailed) => {
+            problems.push("move log can't be replayed".to_string());
+            return Ok(problems);
+        }
+        Err(e) => return Err(e),
+    };
+
+    if stored.state(0)? != replayed.state(0)? {
+        problems.push("stored state differs from replayed state".to_string());
+    }
+    match (status, replayed.finished()) {
+        (GameStatus::Finished, false) => {
+            problems.push("game is finished, but replay isn't".to_string())
+        }
+        (GameStatus::Running, true) => {
+            problems.push("game is running, but replay is finished".to_string())
+        }
+        _ => {}
+    }
+    if stored.outcome() != replayed.outcome() {
+        problems.push(format!(
+            "stored outcome {:?} differs from replayed outcome {:?}",
+            stored.outcome(),
+            replayed.outcome()
+        ));
+    }
+
+    Ok(problems)
+}
+
+impl<'a> From<&'a GameInstance> for InsertDbGame<'a> {
+    fn from(inst: &GameInstance) -> InsertDbGame {
         let state = match &inst.game {
-            Some(g) => serde_json::to_string(&g.state(0)).ok(),
+            Some(g) => g.state(0).ok(),
             None => None,
         };
 
-        let players =
-            serde_json::to_string(&inst.players.iter().map(|id| id.id()).collect::<Vec<i32>>())
-                .unwrap();
         InsertDbGame {
             id: inst.id.0,
             title: &inst.name,
             state,
             owner_id: inst.owner.id(),
-            players,
-            active: if inst.active() { 1 } else { 0 },
             is_public: inst.is_public,
+            game_type: inst.game_type,
+            tournament_id: inst.tournament.map(|id| id.id()),
+            rated: inst.rated,
+            moves: inst.moves,
+            status: inst.status().as_str(),
+            started_at: inst.started_at,
+            finished_at: inst.finished_at,
+            last_move_at: inst.last_move_at,
+            version: inst.version,
         }
     }
 }
 
-pub struct GameManager<G: Game> {
-    active_games: HashMap<GameId, GameInstance<G>>,
+/// A cached game. Each game has its own lock, so requests for different games don't wait on each other
+type CachedGame = Arc<Mutex<GameInstance>>;
+
+/// The cache of active games.
+/// To avoid deadlocks, a game's lock is never taken while holding the manager's lock (but the manager's lock may be taken while holding a game's lock)
+pub struct GameManager {
+    active_games: HashMap<GameId, CachedGame>,
 }
 
-impl<G: Game> Default for GameManager<G> {
-    fn default() -> GameManager<G> {
+impl Default for GameManager {
+    fn default() -> GameManager {
         GameManager {
             active_games: HashMap::new(),
         }
     }
 }
 
-struct AppState<'a, G: Game> {
-    manager: &'a RwLock<GameManager<G>>,
-    db: DBConn,
+/// Wakes requests waiting for a game to change when it is saved
+#[derive(Default)]
+struct GameWatch {
+    /// number of times the game has been saved while it was watched
+    saves: Mutex<u64>,
+    saved: Condvar,
 }
 
-impl<'a, G: Game> AppState<'a, G> {
-    #[allow(unused_must_use)]
-    pub fn new(db: DBConn, manager: &'a RwLock<GameManager<G>>) -> Self {
-        AppState { db, manager }
-    }
+/// Games that requests are waiting on
+type GameWatches = HashMap<GameId, Arc<GameWatch>>;
 
-    /// load a game from the database (only, not active_games)
-    fn load_game_from_db(&self, game_id: GameId) -> Result<GameInstance<G>, Error> {
-        use crate::schema::db_games;
+/// Number of requests each player has waiting for their turn
+type PlayerWaits = HashMap<PlayerId, usize>;
 
-        db_games::dsl::db_games
-            .find(&game_id.0)
-            .first::<DbGame>(&*self.db)
-            .map_or_else(
-                |err| Err(Error::DBError(err)),
-                |entry| Ok(GameInstance::<G>::try_from(entry)?),
-            )
-    }
+/// Clients streaming the events of each game
+type GameStreams = HashMap<GameId, Vec<Sender<String>>>;
 
-    /// save a game to the database
-    fn save_game_to_db<'l>(
-        &self,
-        game: &GameInstance<G>,
-        manager_lock: RwLockWriteGuard<'l, GameManager<G>>,
-    ) -> Result<RwLockWriteGuard<'l, GameManager<G>>, Error> {
-        use crate::schema::db_games;
-        let new_entry = InsertDbGame::from(game);
+/// Bots connected over websockets, which are sent the games that are waiting on them.
+/// Each connection is given an id, so it can be removed when it closes
+#[derive(Default)]
+struct BotSockets {
+    next_id: usize,
+    players: HashMap<PlayerId, Vec<(usize, Sender<BotEvent>)>>,
+}
 
-        let res = diesel::update(db_games::dsl::db_games.find(game.id.id()))
-            .set(&new_entry)
-            .execute(&*self.db)
-            .map_or_else(|e| Err(Error::DBError(e)), |_| Ok(manager_lock));
+/// A change made to a game, sent to clients streaming the game's events
+#[derive(Clone, Copy)]
+enum GameEvent<'a> {
+    /// the game's state when the stream started
+    State,
+    Join,
+    Leave,
+    Start,
+    Move {
+        seat: GamePlayer,
+        player_move: &'a str,
+    },
+    Finish,
+}
 
-        res
+impl GameEvent<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            GameEvent::State => "state",
+            GameEvent::Join => "join",
+            GameEvent::Leave => "leave",
+            GameEvent::Start => "start",
+            GameEvent::Move { .. } => "move",
+            GameEvent::Finish => "finish",
+        }
     }
-
-    /// create a new game entry in the db and in active_games
-    fn new_game(&self, name: &str, owner: PlayerId) -> Result<GameId, Error> {
-        use crate::schema::db_games;
-
-        let game = NewDbGame {
-            players: serde_json::to_string(&Vec::<Vec<String>>::new())?,
-            active: 1,
-            owner_id: owner.id(),
-            title: name,
-            state: None,
-            is_public: true,
+    /// check if there are no events in the game after this one
+    fn ends_game(&self) -> bool {
+        matches!(self, GameEvent::Finish)
+    }
+    /// get the event's data: the move (for moves), and the game after the change (as seen by the first player)
+    fn data(&self, app: &AppState, game: &GameInstance) -> Result<EventResp, Error> {
+        let (seat, player_move) = match *self {
+            GameEvent::Move { seat, player_move } => (Some(seat), Some(player_move.to_string())),
+            _ => (None, None),
         };
 
-        let inserted_game = diesel::insert_into(db_games::table)
-            .values(&game)
-            .get_result::<DbGame>(&*self.db)?;
-        let id = GameId(inserted_game.id);
+        Ok(EventResp {
+            seat,
+            player_move,
+            game: game_resp(app, game, 0)?,
+        })
+    }
+}
 
-        let mut manager = self.manager.write().unwrap();
-        manager.active_games.insert(
-            id,
-            GameInstance::<G> {
-                game: None,
-                players: vec![],
-                name: name.to_string(),
-                owner,
-                id,
-                is_public: inserted_game.is_public,
-            },
-        );
+/// size of the chunks event streams are sent in
+const EVENT_CHUNK_SIZE: u64 = 65536;
+/// how long event streams go without a message before a comment is sent, so disconnected clients are noticed
+const EVENT_KEEPALIVE: Duration = Duration::from_secs(30);
+/// maximum number of event streams a client can have open at once
+const MAX_STREAMS_PER_CLIENT: usize = 4;
 
-        Ok(id)
-    }
+/// format an event as a message in an event stream
+fn event_message(name: &str, data: &EventResp) -> Result<String, Error> {
+    Ok(format!(
+        "event: {}\ndata: {}\n\n",
+        name,
+        serde_json::to_string(data)?
+    ))
+}
 
-    /// get the game with the given id.
-    /// possibly loads it from the database/cache, and may remove or insert it into the cache
-    fn get_game(&self, game_id: GameId) -> Result<GameInstance<G>, Error> {
-        // check active_games for cached game
-        let mut manager = self.manager.write().unwrap();
-        let cached = manager.active_games.get(&game_id);
-        match cached {
-            Some(game) => {
-                let res = game.clone();
-                // if game isn't active, remove from active games
-                if !res.active() {
-                    let mut manager = self.save_game_to_db(&res, manager)?;
-                    manager.active_games.remove(&game_id);
-                }
+/// The number of event streams open, in total and from each client.
+/// Each stream holds a worker thread until its game ends, so they are limited
+struct StreamClients {
+    max: usize,
+    total: usize,
+    clients: HashMap<Option<IpAddr>, usize>,
+}
 
-                Ok(res)
-            }
-            None => {
-                let game = self.load_game_from_db(game_id)?;
-                // if the game isn't finished, put it into active_games
-                if game.active() {
-                    manager.active_games.insert(game_id, game.clone());
-                }
+/// An open event stream's place in the limits on streams, which is given back when the stream is dropped
+pub struct StreamSlot {
+    clients: Arc<Mutex<StreamClients>>,
+    client: Option<IpAddr>,
+}
 
-                Ok(game)
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.total -= 1;
+        if let Some(count) = clients.clients.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                clients.clients.remove(&self.client);
             }
         }
     }
+}
 
-    /// save a game
-    /// possibly saves to the cache or db
-    fn save_game(&self, game: GameInstance<G>) -> Result<(), Error> {
-        let manager = self.manager.write().unwrap();
-        if game.active() {
-            // TODO: this isn't needed, but cache needs to be flushed to db when app is shut down
-            let mut manager = self.save_game_to_db(&game, manager)?;
-            manager.active_games.insert(game.id, game);
-        } else {
-            let mut manager = self.save_game_to_db(&game, manager)?;
-            manager.active_games.remove(&game.id);
-        }
+/// The messages in a game's event stream: the game's state, then each event as it happens
+pub struct GameEvents {
+    first: Option<String>,
+    events: Receiver<String>,
+    _slot: StreamSlot,
+}
 
-        Ok(())
+impl Iterator for GameEvents {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        match self.events.recv_timeout(EVENT_KEEPALIVE) {
+            Ok(message) => Some(message),
+            // an empty message is sent as just a comment
+            Err(RecvTimeoutError::Timeout) => Some(String::new()),
+            Err(RecvTimeoutError::Disconnected) => None,
+        }
     }
+}
 
-    /// add a player to the given game
-    fn join_game(&self, game_id: GameId, player_id: PlayerId) -> Result<(), Error> {
-        let mut game = self.get_game(game_id)?;
-        if game.active() {
-            Err(Error::GameAlreadyStarted)
-        } else {
-            if game.players.contains(&player_id) {
-                Err(Error::AlreadyInGame)
-            } else {
-                game.players.push(player_id);
-                self.save_game(game)?;
+/// The events of a game, read as a stream of server-sent events
+pub type GameEventStream = MessageReader<GameEvents>;
 
-                Ok(())
+/// A connected bot's place in the bots sent turns, which is removed when the bot's connection is done
+struct BotSlot {
+    bots: Arc<Mutex<BotSockets>>,
+    player: PlayerId,
+    id: usize,
+}
+
+impl Drop for BotSlot {
+    fn drop(&mut self) {
+        let mut bots = self.bots.lock().unwrap();
+        if let Some(senders) = bots.players.get_mut(&self.player) {
+            senders.retain(|(id, _)| *id != self.id);
+            if senders.is_empty() {
+                bots.players.remove(&self.player);
             }
         }
     }
+}
 
-    /// remove a player from the given game (if it has not started)
-    fn leave_game(&self, game_id: GameId, player_id: PlayerId) -> Result<(), Error> {
-        let mut game = self.get_game(game_id)?;
+/// how long a bot has to connect and authenticate, and how many times it can try to authenticate
+const BOT_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+const BOT_AUTH_ATTEMPTS: usize = 3;
+/// how long writing a message to a bot can take before the bot is disconnected
+const BOT_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
 
-        if !game.players.contains(&player_id) {
-            Err(Error::NotJoinedGame)
-        } else if game.started() {
-            Err(Error::GameAlreadyStarted)
-        } else {
-            game.players
-                .iter()
-                .position(|id| *id == player_id)
-                .map(|pos| game.players.remove(pos));
-            self.save_game(game)?;
-            Ok(())
+/// Something a bot's connection has to handle
+enum BotEvent {
+    /// a game started waiting on the bot
+    Turn(GameId),
+    /// the bot sent data
+    Readable,
+}
+
+/// The first message a bot sends over its websocket, to authenticate itself
+#[derive(Deserialize)]
+struct BotAuth {
+    api_key: String,
+}
+
+/// A move sent by a bot over its websocket
+#[derive(Deserialize)]
+struct BotMove {
+    game_id: i32,
+    #[serde(rename = "move")]
+    player_move: String,
+}
+
+/// A message sent to a bot over its websocket
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BotMessage {
+    /// the bot's api key was accepted
+    Authenticated { id: i32 },
+    /// a game is waiting on a move from the bot
+    Turn {
+        game_id: i32,
+        /// the game's version, which is higher in later turns (turns may arrive out of order)
+        version: i32,
+        #[serde(flatten)]
+        game: Box<GameResp>,
+    },
+    /// the bot's move was made
+    Moved { game_id: i32, success: bool },
+    /// something the bot sent failed
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        game_id: Option<i32>,
+        #[serde(flatten)]
+        error: ErrorResp,
+    },
+}
+
+impl BotMessage {
+    fn error(game_id: Option<i32>, err: Error) -> BotMessage {
+        BotMessage::Error {
+            game_id,
+            error: ErrorResp::from(err),
         }
     }
+}
 
-    /// start the game with the given id (ie -- give it a state)
-    /// player_id must be the owner of the game
-    fn start_game(&self, game_id: GameId, player_id: PlayerId) -> Result<(), Error> {
-        let mut game = self.get_game(game_id)?;
+/// times a webhook is posted before giving up
+const WEBHOOK_ATTEMPTS: u32 = 5;
+/// how long to wait before posting a webhook again after the first failure (doubling after each failure)
+const WEBHOOK_BACKOFF: Duration = Duration::from_secs(1);
+/// how long to wait for a webhook's url to respond
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// threads posting webhooks. Each webhook takes up one until it is posted or given up on
+const WEBHOOK_WORKERS: usize = 8;
+/// webhooks that can wait for a thread to post them. Webhooks sent while the queue is full are dropped
+const WEBHOOK_QUEUE_SIZE: usize = 256;
+/// header with the hmac-sha256 of a webhook's body, keyed with the user's webhook secret
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Codekata-Signature";
 
-        if game.owner != player_id {
-            Err(Error::NotGameOwner)
-        } else if game.started() {
-            Err(Error::GameAlreadyStarted)
-        } else {
-            let num_players = game.players.len();
-            if G::check_num_players(num_players) {
-                game.game = Some(Box::new(G::new_with_players(num_players)));
-                self.save_game(game)?;
+/// sign a webhook's body with a user's webhook secret, as hex
+fn webhook_signature(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("hmac takes keys of any length");
+    mac.update(body.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
 
-                Ok(())
-            } else {
-                Err(Error::InvalidNumPlayers)
-            }
+/// check if an address is reachable from the internet, and not on the server's own network
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || first == 0
+                // shared address space (100.64.0.0/10)
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local (fc00::/7) and link local (fe80::/10) addresses
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+                && ip
+                    .to_ipv4()
+                    .map_or(true, |ip| is_public_address(IpAddr::V4(ip)))
         }
     }
+}
 
-    /// get a list of all games ids in descending order
-    fn list_games(&self) -> Result<Vec<i32>, Error> {
-        use crate::schema::db_games;
+/// Resolves the hosts webhooks are posted to, refusing hosts with addresses on the server's own network
+/// (unless they are allowed), so webhooks can't be used to reach services that aren't public
+#[derive(Clone, Default)]
+struct WebhookResolver {
+    allowed_hosts: Arc<RwLock<HashSet<String>>>,
+}
 
-        let ids = db_games::dsl::db_games
-            .filter(db_games::dsl::is_public.eq(true))
-            .select(db_games::dsl::id)
-            .order(db_games::id.desc())
-            .load::<i32>(&*self.db)?;
+impl ureq::Resolver for WebhookResolver {
+    /// resolve a host and port, in the form "host:port"
+    fn resolve(&self, netloc: &str) -> io::Result<Vec<SocketAddr>> {
+        let addrs = netloc.to_socket_addrs()?.collect::<Vec<SocketAddr>>();
+        let host = netloc.rsplitn(2, ':').last().unwrap_or(netloc);
+        if !self.allowed_hosts.read().unwrap().contains(host)
+            && addrs.iter().any(|addr| !is_public_address(addr.ip()))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "webhook host isn't public",
+            ));
+        }
 
-        Ok(ids)
+        Ok(addrs)
     }
+}
 
-    /// create a new tournament
-    fn new_tournament(&self, name: &str, owner: PlayerId) -> Result<TournamentId, Error> {
-        use crate::schema::tournaments;
+/// A webhook waiting to be posted
+struct Webhook {
+    url: String,
+    secret: String,
+    body: String,
+}
 
-        let tournament = NewTournament {
-            owner_id: owner.id(),
-            name,
-            players: vec![],
-            games: None,
-        };
+/// post a webhook, trying again with backoff until it succeeds
+fn post_webhook(agent: &ureq::Agent, webhook: Webhook) {
+    let signature = format!(
+        "sha256={}",
+        webhook_signature(&webhook.secret, &webhook.body)
+    );
+    let post = || {
+        agent
+            .post(&webhook.url)
+            .set("Content-Type", "application/json")
+            .set(WEBHOOK_SIGNATURE_HEADER, &signature)
+            .timeout(WEBHOOK_TIMEOUT)
+            .send_string(&webhook.body)
+            .ok()
+    };
 
-        let inserted = diesel::insert_into(tournaments::table)
-            .values(&tournament)
-            .get_result::<Tournament>(&*self.db)?;
+    let mut backoff = WEBHOOK_BACKOFF;
+    for _ in 1..WEBHOOK_ATTEMPTS {
+        if post() {
+            return;
+        }
+        thread::sleep(backoff);
+        backoff *= 2;
+    }
+    if !post() {
+        eprintln!(
+            "posting webhook to {} failed {} times, giving up",
+            webhook.url, WEBHOOK_ATTEMPTS
+        );
+    }
+}
 
-        Ok(TournamentId(inserted.id))
+/// start the threads that post webhooks, returning the queue to send webhooks to them through
+fn start_webhook_workers(resolver: &WebhookResolver) -> SyncSender<Webhook> {
+    let (sender, receiver) = mpsc::sync_channel(WEBHOOK_QUEUE_SIZE);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..WEBHOOK_WORKERS {
+        let receiver = receiver.clone();
+        let mut agent = ureq::Agent::new();
+        agent.set_resolver(resolver.clone());
+        thread::spawn(move || loop {
+            let webhook = match receiver.lock().unwrap().recv() {
+                Ok(webhook) => webhook,
+                // the server was dropped
+                Err(_) => return,
+            };
+            post_webhook(&agent, webhook);
+        });
     }
 
-    /// find a tournament from db
-    fn get_tournament(&self, id: TournamentId) -> Result<Tournament, Error> {
-        use crate::schema::tournaments;
+    sender
+}
 
-        Ok(tournaments::dsl::tournaments
-            .find(id.0)
-            .first::<Tournament>(&*self.db)?)
+/// send a message to a bot over its websocket
+fn write_bot_message(
+    socket: &mut WebSocket<TcpStream>,
+    message: &BotMessage,
+) -> Result<(), tungstenite::Error> {
+    let text = serde_json::to_string(message)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    socket.write_message(Message::Text(text))
+}
+
+/// The games hosted by the server: the kinds of games available, the cache of active games, and the games being waited on or streamed.
+/// Clones share the same cache
+#[derive(Clone)]
+pub struct GameServer {
+    manager: Arc<RwLock<GameManager>>,
+    kinds: Arc<GameRegistry>,
+    watches: Arc<Mutex<GameWatches>>,
+    waits: Arc<Mutex<PlayerWaits>>,
+    streams: Arc<Mutex<GameStreams>>,
+    stream_clients: Arc<Mutex<StreamClients>>,
+    bots: Arc<Mutex<BotSockets>>,
+    webhooks: SyncSender<Webhook>,
+    webhook_resolver: WebhookResolver,
+}
+
+impl GameServer {
+    pub fn new(kinds: GameRegistry) -> GameServer {
+        let webhook_resolver = WebhookResolver::default();
+        GameServer {
+            manager: Arc::new(RwLock::new(GameManager::default())),
+            kinds: Arc::new(kinds),
+            watches: Arc::new(Mutex::new(HashMap::new())),
+            waits: Arc::new(Mutex::new(HashMap::new())),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            // no streams are allowed until a limit is set
+            stream_clients: Arc::new(Mutex::new(StreamClients {
+                max: 0,
+                total: 0,
+                clients: HashMap::new(),
+            })),
+            bots: Arc::new(Mutex::new(BotSockets::default())),
+            webhooks: start_webhook_workers(&webhook_resolver),
+            webhook_resolver,
+        }
     }
 
-    /// save a tournament to db
-    fn save_tournament(&self, tournament: &Tournament) -> Result<(), Error> {
-        use crate::schema::tournaments;
+    /// allow webhooks to be posted to the given hosts, even if they are on the server's own network
+    pub fn set_webhook_allowed_hosts(&self, hosts: Vec<String>) {
+        *self.webhook_resolver.allowed_hosts.write().unwrap() = hosts.into_iter().collect();
+    }
+
+    /// check that a url can have webhooks posted to it: it is http or https, and its host is public (or allowed)
+    pub fn check_webhook_url(&self, url: &str) -> Result<(), Error> {
+        let url = Url::parse(url).map_err(|_| Error::InvalidWebhookUrl)?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(Error::InvalidWebhookUrl);
+        }
+        let (host, port) = match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => (host, port),
+            _ => return Err(Error::InvalidWebhookUrl),
+        };
+        ureq::Resolver::resolve(&self.webhook_resolver, &format!("{}:{}", host, port))
+            .map_err(|_| Error::InvalidWebhookUrl)?;
 
-        diesel::update(tournaments::dsl::tournaments.find(tournament.id))
-            .set(tournament)
-            .execute(&*self.db)?;
         Ok(())
     }
 
-    /// join a tournament
-    fn join_tournament(
-        &self,
-        tournament_id: TournamentId,
-        player_id: PlayerId,
-    ) -> Result<(), Error> {
-        let mut tournament = self.get_tournament(tournament_id)?;
-
-        if tournament.games.is_some() {
-            Err(Error::GameAlreadyStarted)
-        } else {
-            if tournament.players.contains(&player_id.id()) {
-                Err(Error::AlreadyInGame)
-            } else {
-                tournament.players.push(player_id.id());
-                self.save_tournament(&tournament)?;
+    /// set the maximum number of event streams open at once, across all clients
+    pub fn set_max_streams(&self, max: usize) {
+        self.stream_clients.lock().unwrap().max = max;
+    }
 
-                Ok(())
-            }
+    /// take a place for a client's event stream, unless the client or the server already has too many open
+    fn open_stream(&self, client: Option<IpAddr>) -> Result<StreamSlot, Error> {
+        let mut clients = self.stream_clients.lock().unwrap();
+        let open = clients.clients.get(&client).copied().unwrap_or(0);
+        if clients.total >= clients.max || open >= MAX_STREAMS_PER_CLIENT {
+            return Err(Error::TooManyStreams);
         }
+        clients.total += 1;
+        clients.clients.insert(client, open + 1);
+
+        Ok(StreamSlot {
+            clients: self.stream_clients.clone(),
+            client,
+        })
     }
 
-    /// leave a tournament
-    fn leave_tournament(&self, id: TournamentId, player_id: PlayerId) -> Result<(), Error> {
-        let mut tournament = self.get_tournament(id)?;
+    /// load all running games from the database into the cache, making any moves they are missing from their move logs.
+    /// games that can't be loaded are logged and left out of the cache. returns the number of games loaded
+    pub fn warm_up(&self, conn: &PgConnection) -> Result<usize, Error> {
+        use crate::schema::db_games;
 
-        if tournament.games.is_some() {
-            Err(Error::GameAlreadyStarted)
-        } else {
-            if !tournament.players.contains(&player_id.id()) {
-                Err(Error::NotJoinedGame)
-            } else {
-                tournament
-                    .players
-                    .iter()
-                    .position(|id| *id == player_id.id())
-                    .map(|pos| tournament.players.remove(pos));
-                self.save_tournament(&tournament)?;
-                Ok(())
+        let entries = db_games::dsl::db_games
+            .filter(db_games::dsl::status.eq(GameStatus::Running.as_str()))
+            .load::<DbGame>(conn)?;
+        let ids = entries.iter().map(|entry| entry.id).collect::<Vec<i32>>();
+        let mut players = load_game_players(conn, ids)?;
+
+        let mut games = vec![];
+        for entry in entries {
+            let id = entry.id;
+            let game_players = players.remove(&id).unwrap_or_default();
+            let game =
+                GameInstance::from_db(entry, game_players, &self.kinds).and_then(|mut game| {
+                    catch_up_from_log(conn, &mut game)?;
+                    Ok(game)
+                });
+            match game {
+                Ok(game) => games.push(game),
+                Err(err) => eprintln!(
+                    "running game {} can't be loaded: {}",
+                    id,
+                    ErrorResp::from(err).error
+                ),
             }
         }
-    }
 
-    /// start a tournament and generate match schedule
-    fn start_tournament(&self, id: TournamentId, player_id: PlayerId) -> Result<(), Error> {
-        let mut tournament = self.get_tournament(id)?;
+        let mut manager = self.manager.write().unwrap();
+        for game in games {
+            manager
+                .active_games
+                .insert(game.id, Arc::new(Mutex::new(game)));
+        }
 
-        if tournament.owner_id != player_id.id() {
-            Err(Error::NotGameOwner)
-        } else if tournament.games.is_some() {
-            Err(Error::GameAlreadyStarted)
-        } else {
-            for match_players in tournament
-                .players
-                .iter()
-                .combinations(TOURNAMENT_GAME_PLAYERS)
-            {
-                // TODO
-            }
+        Ok(manager.active_games.len())
+    }
 
-            panic!("todo");
+    /// start watching a game for saves
+    fn watch_game(&self, game_id: GameId) -> Arc<GameWatch> {
+        self.watches
+            .lock()
+            .unwrap()
+            .entry(game_id)
+            .or_default()
+            .clone()
+    }
 
-            Ok(())
+    /// stop watching a game, and forget about it if no other requests are watching it
+    fn unwatch_game(&self, game_id: GameId, watch: Arc<GameWatch>) {
+        let mut watches = self.watches.lock().unwrap();
+        // one reference is in watches, and the other is this request's
+        if Arc::strong_count(&watch) == 2 {
+            watches.remove(&game_id);
         }
     }
-}
 
-pub type AppReqState<'a> = State<'a, RwLock<GameManager<crate::GameType>>>;
+    /// count a request waiting for a player's turn, unless the player already has too many waiting
+    fn start_waiting(&self, player: PlayerId) -> Result<(), Error> {
+        let mut waits = self.waits.lock().unwrap();
+        let count = waits.entry(player).or_default();
+        if *count >= MAX_WAITS_PER_PLAYER {
+            return Err(Error::TooManyWaits);
+        }
+        *count += 1;
 
-#[derive(Serialize, Debug)]
-pub struct GameResp<G: Game> {
-    name: String,
-    owner_id: i32,
-    state: Option<G::State>,
-    players: Vec<String>,
-    player_ids: Vec<i32>,
-    active: bool,
-    started: bool,
-    waiting_on: Vec<bool>,
-    outcome: String,
-}
+        Ok(())
+    }
 
-fn game_get_internal(
-    player_id: i32,
-    id: i32,
-    db: DBConn,
-    state: AppReqState,
-) -> Result<Json<GameResp<crate::GameType>>, Json<ErrorResp>> {
-    let app = AppState::new(db, &*state);
+    /// stop counting a request waiting for a player's turn
+    fn stop_waiting(&self, player: PlayerId) {
+        let mut waits = self.waits.lock().unwrap();
+        if let Some(count) = waits.get_mut(&player) {
+            *count -= 1;
+            if *count == 0 {
+                waits.remove(&player);
+            }
+        }
+    }
 
-    let game = app.get_game(GameId(id))?;
+    /// wait until it is a player's turn in a game, the game ends, or the timeout passes.
+    /// the database is only connected to while checking the game, not while waiting.
+    /// returns the game and if the player needs to make a move
+    fn wait_for_turn(
+        &self,
+        db: &LazyDBConn,
+        game_id: GameId,
+        player: PlayerId,
+        timeout: Duration,
+    ) -> Result<(GameInstance, bool), Error> {
+        // each waiting request holds a worker thread, so one player can't take them all
+        self.start_waiting(player)?;
+        let deadline = Instant::now() + timeout;
+        let watch = self.watch_game(game_id);
 
-    let players = game
-        .players
-        .iter()
-        .map(|id| -> Result<String, Error> {
-            use crate::schema::users;
+        let res = (|| loop {
+            // saves made after loading the game wake the wait below
+            let saves = *watch.saves.lock().unwrap();
+            let game = AppState::new(db.get()?, self).get_game(game_id)?;
+            let needed = game.waiting_on(game.get_player_index(player)?);
+            let ended = game.status() == GameStatus::Finished;
+            let now = Instant::now();
+            if needed || ended || now >= deadline {
+                return Ok((game, needed));
+            }
 
-            Ok(users::dsl::users
-                .find(id.id())
-                .first::<User>(&*app.db)?
-                .display_name)
-        })
-        .collect::<Result<Vec<String>, Error>>()?;
+            let (_saves, _) = watch
+                .saved
+                .wait_timeout_while(watch.saves.lock().unwrap(), deadline - now, |s| *s == saves)
+                .unwrap();
+        })();
 
-    let player_ids = game.players.iter().map(|id| id.id()).collect::<Vec<i32>>();
+        self.unwatch_game(game_id, watch);
+        self.stop_waiting(player);
+        res
+    }
 
-    let waiting_on = if game.active() {
-        game.players
-            .iter()
-            .enumerate()
-            .map(|(index, _)| {
-                game.game
-                    .as_ref()
-                    .map_or(false, |g| g.waiting_on(index as u32))
-            })
-            .collect::<Vec<bool>>()
-    } else {
-        Vec::<bool>::new()
-    };
+    /// accept websocket connections from bots on the given address, serving each on its own thread
+    pub fn listen_bots(
+        &self,
+        pool: DBPool,
+        address: &str,
+        max_bots: usize,
+    ) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(address)?;
+        let local_address = listener.local_addr()?;
+        let server = self.clone();
+        // connections are only counted by this thread, so checking and adding to the count doesn't race
+        let connected = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming().filter_map(Result::ok) {
+                // each connection holds a thread, so connections past the limit are closed right away
+                if connected.load(Ordering::SeqCst) >= max_bots {
+                    continue;
+                }
+                connected.fetch_add(1, Ordering::SeqCst);
+                let (server, pool, connected) = (server.clone(), pool.clone(), connected.clone());
+                thread::spawn(move || {
+                    // the bot's connection is closed either way, so errors aren't reported
+                    let _ = server.serve_bot(&pool, stream);
+                    connected.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
 
-    let game_player_display_for = player_ids
-        .iter()
-        .position(|id| *id == player_id)
-        .map_or(0, |index| index);
+        Ok(local_address)
+    }
 
-    let outcome = game
-        .game
-        .as_ref()
-        .map_or(format!("No Outcome Yet"), |g| match g.outcome() {
-            GameOutcome::Win(player) => format!("{} Wins!", &players[player as usize]),
-            GameOutcome::Tie => format!("Game Tied!"),
-            GameOutcome::Other(msg) => msg,
-            GameOutcome::None => format!("No Outcome Yet"),
-        });
+    /// talk to a bot over a websocket: authenticate it, then send it the games waiting on it and make the moves it sends.
+    /// returns when the bot disconnects
+    fn serve_bot(&self, pool: &DBPool, stream: TcpStream) -> Result<(), tungstenite::Error> {
+        // a clone of the stream is watched for data from the bot, and is shut down when the bot is done
+        let watched = stream.try_clone()?;
+        let res = self.serve_bot_socket(pool, stream, watched.try_clone()?);
+        let _ = watched.shutdown(Shutdown::Both);
 
-    Ok(Json(GameResp {
-        owner_id: game.owner.id(),
-        state: game
-            .game
-            .as_ref()
-            .and_then(|game| Some(game.state(game_player_display_for as u32))),
-        players,
-        player_ids,
-        active: game.active(),
-        started: game.started(),
-        waiting_on,
-        name: game.name,
-        outcome,
-    }))
-}
+        res
+    }
 
-#[get("/game/<id>?<dont_invert>")]
-pub fn game_get_user_authd(
-    id: i32,
-    db: DBConn,
-    state: AppReqState,
-    user: ForwardingUser,
-    dont_invert: Option<bool>,
-) -> Result<Json<GameResp<crate::GameType>>, Json<ErrorResp>> {
-    let player_id = match dont_invert {
-        None | Some(false) => user.0.id,
-        Some(true) => 0,
-    };
-    game_get_internal(player_id, id, db, state)
-}
+    fn serve_bot_socket(
+        &self,
+        pool: &DBPool,
+        stream: TcpStream,
+        watched: TcpStream,
+    ) -> Result<(), tungstenite::Error> {
+        // bots that don't finish connecting and authenticating in time are disconnected
+        let deadline = Instant::now() + BOT_AUTH_TIMEOUT;
+        let timed_out =
+            || io::Error::new(io::ErrorKind::TimedOut, "bot didn't authenticate in time");
+        stream.set_read_timeout(Some(BOT_AUTH_TIMEOUT))?;
+        stream.set_write_timeout(Some(BOT_WRITE_TIMEOUT))?;
+        let mut socket = tungstenite::accept(stream).map_err(|err| match err {
+            HandshakeError::Failure(err) => err,
+            // the handshake only stops partway through if the read timed out
+            HandshakeError::Interrupted(_) => tungstenite::Error::Io(timed_out()),
+        })?;
 
-#[get("/game/<id>?<dont_invert>", rank = 2)]
-pub fn game_get(
-    id: i32,
-    db: DBConn,
-    state: AppReqState,
-    dont_invert: Option<bool>,
-) -> Result<Json<GameResp<crate::GameType>>, Json<ErrorResp>> {
-    game_get_internal(0, id, db, state)
-}
+        let mut attempts = 0;
+        let player = loop {
+            let remaining = deadline
+                .checked_duration_since(Instant::now())
+                .filter(|remaining| *remaining > Duration::from_secs(0))
+                .ok_or_else(timed_out)?;
+            socket.get_mut().set_read_timeout(Some(remaining))?;
+            if let Message::Text(text) = socket.read_message()? {
+                match self.authenticate_bot(pool, &text) {
+                    Ok(player) => break player,
+                    Err(err) => {
+                        write_bot_message(&mut socket, &BotMessage::error(None, err))?;
+                        attempts += 1;
+                        if attempts >= BOT_AUTH_ATTEMPTS {
+                            socket.close(None)?;
+                            return socket.write_pending();
+                        }
+                    }
+                }
+            }
+        };
+        socket.get_mut().set_read_timeout(None)?;
+        write_bot_message(&mut socket, &BotMessage::Authenticated { id: player.id() })?;
 
-#[derive(Serialize)]
-pub struct NeededResp {
-    needed: bool,
-}
+        let (sender, events) = mpsc::channel();
+        // data from the bot is waited for on another thread, which waits again once the data is read
+        let (read, resume) = mpsc::channel();
+        let readable = sender.clone();
+        thread::spawn(move || loop {
+            // peeking returns once there is data (or the bot disconnected), without reading it
+            let _ = watched.peek(&mut [0]);
+            if readable.send(BotEvent::Readable).is_err() || resume.recv().is_err() {
+                return;
+            }
+        });
+        // the bot is added before its games are checked, so no turns are missed
+        let _slot = self.add_bot(player, sender);
+        let mut waiting = match pool
+            .get()
+            .and_then(|db| AppState::new(db, self).games_awaiting_move(player))
+        {
+            Ok(games) => games.iter().map(|game| game.id).collect(),
+            Err(err) => {
+                write_bot_message(&mut socket, &BotMessage::error(None, err))?;
+                vec![]
+            }
+        };
 
-#[get("/game/<id>/move_needed")]
-pub fn game_move_needed(
-    id: i32,
-    db: DBConn,
-    state: AppReqState,
-    user: User,
-) -> Result<Json<NeededResp>, Json<ErrorResp>> {
-    let app = AppState::new(db, &*state);
-    let game = app.load_game_from_db(GameId(id))?;
-    if !game.active() {
-        Ok(Json(NeededResp { needed: false }))
-    } else {
-        let player_index = game.get_player_index(PlayerId::new(user.id))?;
-        let needed = game
-            .game
-            .as_ref()
-            .map_or(false, |game| game.waiting_on(player_index));
-        Ok(Json(NeededResp { needed }))
-    }
-}
+        let mut is_readable = false;
+        loop {
+            if !waiting.is_empty() {
+                waiting.sort_by_key(|game_id| game_id.0);
+                waiting.dedup();
+                match self.bot_turns(pool, player, &waiting) {
+                    Ok(turns) => {
+                        for turn in &turns {
+                            write_bot_message(&mut socket, turn)?;
+                        }
+                    }
+                    Err(err) => write_bot_message(&mut socket, &BotMessage::error(None, err))?,
+                }
+                waiting.clear();
+            }
 
-#[post("/game/<id>/move", data = "<player_move>")]
-pub fn game_move(
-    id: i32,
-    player_move: Form<<crate::GameType as Game>::Move>,
-    db: DBConn,
-    state: AppReqState,
-    user: User,
-) -> Result<Json<SuccessResp>, Json<ErrorResp>> {
-    let app = AppState::new(db, &*state);
-    let mut game = app.get_game(GameId(id))?;
-    if !game.active() {
-        Err(Json(ErrorResp::from(Error::WrongTurn)))
-    } else {
-        let player_index = game.get_player_index(PlayerId::new(user.id))?;
-        match game.game.as_mut() {
-            Some(game_int) => {
-                if game_int.waiting_on(player_index) {
-                    if game_int.make_move(player_index, &*player_move) {
-                        app.save_game(game)?;
-                        Ok(Json(SuccessResp { success: true }))
-                    } else {
-                        Err(Json(ErrorResp::from(Error::InvalidMove)))
+            if is_readable {
+                // read all of the messages the bot sent, which may already have been taken off the socket
+                loop {
+                    socket.get_mut().set_nonblocking(true)?;
+                    let message = socket.read_message();
+                    socket.get_mut().set_nonblocking(false)?;
+                    match message {
+                        Ok(Message::Text(text)) => {
+                            let reply = self.bot_move(pool, player, &text);
+                            write_bot_message(&mut socket, &reply)?;
+                        }
+                        Ok(_) => {}
+                        Err(tungstenite::Error::Io(ref err))
+                            if err.kind() == io::ErrorKind::WouldBlock =>
+                        {
+                            break
+                        }
+                        Err(err) => return Err(err),
                     }
-                } else {
-                    Err(Json(ErrorResp::from(Error::WrongTurn)))
+                }
+                is_readable = false;
+                let _ = read.send(());
+            }
+
+            // wait for the bot to send something or a game to start waiting on it, handling everything that happened
+            let first = events
+                .recv()
+                .expect("the bot's sender is held until it is done");
+            for event in iter::once(first).chain(events.try_iter()) {
+                match event {
+                    BotEvent::Turn(game_id) => waiting.push(game_id),
+                    BotEvent::Readable => is_readable = true,
                 }
             }
-            None => Err(Json(ErrorResp::from(Error::GameNotStarted))),
         }
     }
-}
 
-#[derive(FromForm)]
-pub struct NewGameForm {
-    name: String,
-}
+    /// start sending a bot the games that start waiting on its player, until the returned slot is dropped
+    fn add_bot(&self, player: PlayerId, sender: Sender<BotEvent>) -> BotSlot {
+        let mut bots = self.bots.lock().unwrap();
+        let id = bots.next_id;
+        bots.next_id += 1;
+        bots.players.entry(player).or_default().push((id, sender));
 
-#[post("/game/new", data = "<new_game>")]
-pub fn game_new(
-    new_game: Form<NewGameForm>,
-    db: DBConn,
-    state: AppReqState,
-    user: User,
-) -> Result<Json<IdResp>, Json<ErrorResp>> {
-    let app = AppState::new(db, &*state);
-    let id = app.new_game(&new_game.name, PlayerId::new(user.id));
+        BotSlot {
+            bots: self.bots.clone(),
+            player,
+            id,
+        }
+    }
 
-    match id {
-        Ok(id) => Ok(Json(IdResp { id: id.to_string() })),
-        Err(err) => Err(Json(ErrorResp::from(err))),
+    /// find the player a bot is playing as from its authentication message
+    fn authenticate_bot(&self, pool: &DBPool, message: &str) -> Result<PlayerId, Error> {
+        let auth = serde_json::from_str::<BotAuth>(message)?;
+        // api keys don't use sessions
+        let sessions = RwLock::new(HashMap::new());
+        let user = UserManager::new(pool.get()?, &sessions).find_user_by_api_key(&auth.api_key)?;
+
+        Ok(PlayerId::new(user.id))
     }
-}
 
-#[post("/game/<id>/join")]
-pub fn game_join(
-    id: i32,
-    db: DBConn,
-    state: AppReqState,
-    user: User,
-) -> Result<Json<SuccessResp>, Json<ErrorResp>> {
+    /// get the messages telling a bot which of the given games are waiting on it
+    fn bot_turns(
+        &self,
+        pool: &DBPool,
+        player: PlayerId,
+        game_ids: &[GameId],
+    ) -> Result<Vec<BotMessage>, Error> {
+        let app = AppState::new(pool.get()?, self);
+        let mut turns = vec![];
+        for game_id in game_ids {
+            let game = app.get_game(*game_id)?;
+            if game.waiting_on(game.get_player_index(player)?) {
+                turns.push(BotMessage::Turn {
+                    game_id: game_id.0,
+                    version: game.version,
+                    game: Box::new(game_resp(&app, &game, player.id())?),
+                });
+            }
+        }
+
+        Ok(turns)
+    }
+
+    /// make a move sent by a bot, and get the reply to send it
+    fn bot_move(&self, pool: &DBPool, player: PlayerId, message: &str) -> BotMessage {
+        let bot_move = match serde_json::from_str::<BotMove>(message) {
+            Ok(bot_move) => bot_move,
+            Err(err) => return BotMessage::error(None, Error::from(err)),
+        };
+        let res = pool.get().and_then(|db| {
+            AppState::new(db, self).make_move(
+                GameId(bot_move.game_id),
+                player,
+                &bot_move.player_move,
+                None,
+                None,
+            )
+        });
+
+        match res {
+            Ok(_) => BotMessage::Moved {
+                game_id: bot_move.game_id,
+                success: true,
+            },
+            Err(err) => BotMessage::error(Some(bot_move.game_id), err),
+        }
+    }
+
+    /// save all cached games to the database.
+    /// moves made after a game is saved are still in its move log, and are made again when the game is loaded,
+    /// so games that fail to save are logged and skipped. returns the number of games saved
+    pub fn flush(&self, conn: &PgConnection) -> usize {
+        let cached = self
+            .manager
+            .read()
+            .unwrap()
+            .active_games
+            .values()
+            .cloned()
+            .collect::<Vec<CachedGame>>();
+        let mut saved = 0;
+        for game in &cached {
+            let game = game.lock().unwrap();
+            match write_game(conn, &game) {
+                Ok(_) => saved += 1,
+                Err(err) => eprintln!(
+                    "game {} can't be saved: {}",
+                    game.id.id(),
+                    ErrorResp::from(err).error
+                ),
+            }
+        }
+
+        saved
+    }
+}
+
+struct AppState<'a> {
+    manager: &'a RwLock<GameManager>,
+    kinds: &'a GameRegistry,
+    watches: &'a Mutex<GameWatches>,
+    streams: &'a Mutex<GameStreams>,
+    bots: &'a Mutex<BotSockets>,
+    webhooks: &'a SyncSender<Webhook>,
+    db: DBConn,
+}
+
+impl<'a> AppState<'a> {
+    #[allow(unused_must_use)]
+    pub fn new(db: DBConn, server: &'a GameServer) -> Self {
+        AppState {
+            db,
+            manager: &*server.manager,
+            kinds: &*server.kinds,
+            watches: &*server.watches,
+            streams: &*server.streams,
+            bots: &*server.bots,
+            webhooks: &server.webhooks,
+        }
+    }
+
+    /// get a game from the cache, if it is there
+    fn cached_game(&self, game_id: GameId) -> Option<CachedGame> {
+        self.manager
+            .read()
+            .unwrap()
+            .active_games
+            .get(&game_id)
+            .cloned()
+    }
+
+    /// check if a game is (still) in the cache
+    fn is_cached(&self, game_id: GameId, game: &CachedGame) -> bool {
+        self.manager
+            .read()
+            .unwrap()
+            .active_games
+            .get(&game_id)
+            .map_or(false, |cached| Arc::ptr_eq(cached, game))
+    }
+
+    /// remove a game from the cache, unless it was replaced
+    fn uncache_game(&self, game_id: GameId, game: &CachedGame) {
+        let mut manager = self.manager.write().unwrap();
+        if manager
+            .active_games
+            .get(&game_id)
+            .map_or(false, |cached| Arc::ptr_eq(cached, game))
+        {
+            manager.active_games.remove(&game_id);
+        }
+    }
+
+    /// load a game from the database (only, not active_games)
+    fn load_game_from_db(&self, game_id: GameId) -> Result<GameInstance, Error> {
+        use crate::schema::db_games;
+
+        let entry = db_games::dsl::db_games
+            .find(&game_id.0)
+            .first::<DbGame>(&*self.db)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => Error::InvalidGameId,
+                e => Error::DBError(e),
+            })?;
+        let mut game = games_from_db(&*self.db, vec![entry], self.kinds)?.remove(0)?;
+        catch_up_from_log(&*self.db, &mut game)?;
+
+        Ok(game)
+    }
+
+    /// create a new game entry of the given kind in the db and in active_games
+    fn new_game(
+        &self,
+        name: &str,
+        owner: PlayerId,
+        game_type: &str,
+        rated: bool,
+    ) -> Result<GameId, Error> {
+        use crate::schema::db_games;
+
+        let kind = self.kinds.get(game_type)?;
+        let game = NewDbGame {
+            owner_id: owner.id(),
+            title: name,
+            state: None,
+            is_public: true,
+            game_type: kind.id,
+            tournament_id: None,
+            rated,
+            moves: 0,
+            status: GameStatus::Lobby.as_str(),
+            started_at: None,
+        };
+
+        let inserted_game = diesel::insert_into(db_games::table)
+            .values(&game)
+            .get_result::<DbGame>(&*self.db)?;
+        let id = GameId(inserted_game.id);
+
+        let mut manager = self.manager.write().unwrap();
+        manager.active_games.insert(
+            id,
+            Arc::new(Mutex::new(GameInstance {
+                game: None,
+                game_type: kind.id,
+                players: vec![],
+                name: name.to_string(),
+                owner,
+                id,
+                tournament: None,
+                rated,
+                moves: 0,
+                created_at: inserted_game.created_at,
+                started_at: None,
+                finished_at: None,
+                last_move_at: None,
+                version: inserted_game.version,
+                is_public: inserted_game.is_public,
+            })),
+        );
+
+        Ok(id)
+    }
+
+    /// create a rated game of the given kind with the given players, and start it immediately
+    fn new_started_game(
+        &self,
+        name: &str,
+        owner: PlayerId,
+        game_type: &str,
+        players: &[PlayerId],
+        tournament: Option<TournamentId>,
+    ) -> Result<GameId, Error> {
+        use crate::schema::db_games;
+
+        let kind = self.kinds.get(game_type)?;
+        if !kind.check_num_players(players.len()) {
+            return Err(Error::InvalidNumPlayers);
+        }
+        let state = kind.new_with_players(players.len()).state(0)?;
+
+        let game = NewDbGame {
+            owner_id: owner.id(),
+            title: name,
+            state: Some(state),
+            is_public: true,
+            game_type: kind.id,
+            tournament_id: tournament.map(|id| id.id()),
+            rated: true,
+            moves: 0,
+            status: GameStatus::Running.as_str(),
+            started_at: Some(SystemTime::now()),
+        };
+
+        let inserted_game = diesel::insert_into(db_games::table)
+            .values(&game)
+            .get_result::<DbGame>(&*self.db)?;
+        let id = GameId(inserted_game.id);
+        write_game_players(&*self.db, id, players)?;
+
+        Ok(id)
+    }
+
+    /// get the game with the given id.
+    /// possibly loads it from the database/cache, and may remove or insert it into the cache
+    fn get_game(&self, game_id: GameId) -> Result<GameInstance, Error> {
+        // check active_games for cached game
+        match self.cached_game(game_id) {
+            Some(cached) => {
+                let res = cached.lock().unwrap().clone();
+                // if game isn't active, remove from active games
+                if !res.active() {
+                    write_game(&*self.db, &res)?;
+                    self.uncache_game(game_id, &cached);
+                }
+
+                Ok(res)
+            }
+            None => {
+                let game = self.load_game_from_db(game_id)?;
+                // if the game isn't finished, put it into active_games (unless another request already did)
+                if game.active() {
+                    let cached = self
+                        .manager
+                        .write()
+                        .unwrap()
+                        .active_games
+                        .entry(game_id)
+                        .or_insert_with(|| Arc::new(Mutex::new(game)))
+                        .clone();
+                    let res = cached.lock().unwrap().clone();
+                    return Ok(res);
+                }
+
+                Ok(game)
+            }
+        }
+    }
+
+    /// save a game after the given change was made to it, updating its timestamps and version
+    /// possibly saves to the cache or db
+    /// fails if the game was changed (by another request) since it was loaded
+    fn save_game(&self, game: GameInstance, event: GameEvent) -> Result<(), Error> {
+        self.save_game_with(game, event, &|_| Ok(()))
+    }
+
+    /// save a game after the given change was made to it, making the given db changes in the same transaction as the game.
+    /// the cache is only updated, and the change only sent to clients, once the transaction has committed,
+    /// so callers shouldn't call this inside a transaction of their own
+    fn save_game_with(
+        &self,
+        mut game: GameInstance,
+        event: GameEvent,
+        write: &dyn Fn(&GameInstance) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let (game, just_finished) = loop {
+            if let Some(cached) = self.cached_game(game.id) {
+                let mut cached_game = cached.lock().unwrap();
+                // the game may have been removed from the cache while waiting for its lock
+                if !self.is_cached(game.id, &cached) {
+                    continue;
+                }
+                // the cached copy is newer than the db, so compare against it
+                if cached_game.version != game.version {
+                    return Err(Error::GameChanged);
+                }
+                game.version += 1;
+                game.update_timestamps(Some(&cached_game));
+                // if the cached copy of the game is active, the game is running or just finished
+                let was_active = cached_game.active();
+                let just_finished = was_active && game.status() == GameStatus::Finished;
+
+                // moves in running games are only saved to the cache (they are in the move log, and the cache is flushed to the db on shutdown),
+                // but games that just finished are saved to the db, along with their players' new ratings
+                self.db.transaction::<_, Error, _>(|| {
+                    write(&game)?;
+                    if (!game.active() || !was_active) && !write_game(&*self.db, &game)? {
+                        return Err(Error::GameChanged);
+                    }
+                    if just_finished && game.rated {
+                        self.update_ratings(&game)?;
+                    }
+                    Ok(())
+                })?;
+                // requests that already have the cached game see it finish
+                *cached_game = game.clone();
+                if !game.active() {
+                    self.uncache_game(game.id, &cached);
+                }
+                // events are sent while the game is locked, so streams get them in the order they happened
+                self.send_event(&game, event);
+                if just_finished {
+                    self.send_event(&game, GameEvent::Finish);
+                }
+                break (game, just_finished);
+            } else {
+                // the cache is locked while the game is saved, so the game isn't loaded into it in the meantime
+                let mut manager = self.manager.write().unwrap();
+                if manager.active_games.contains_key(&game.id) {
+                    continue;
+                }
+                // the db write only succeeds if the db still has the version the game was loaded at
+                game.version += 1;
+                game.update_timestamps(None);
+                self.db.transaction::<_, Error, _>(|| {
+                    write(&game)?;
+                    if !write_game(&*self.db, &game)? {
+                        return Err(Error::GameChanged);
+                    }
+                    Ok(())
+                })?;
+                if game.active() {
+                    manager
+                        .active_games
+                        .insert(game.id, Arc::new(Mutex::new(game.clone())));
+                }
+                self.send_event(&game, event);
+                break (game, false);
+            }
+        };
+        self.game_saved(game.id);
+        self.notify_bots(&game);
+        self.send_webhooks(&game);
+
+        if just_finished {
+            self.game_finished(&game)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// send an event to the clients streaming a game's events. Failures are logged, since the change is already saved.
+    /// streams end once the game ends
+    fn send_event(&self, game: &GameInstance, event: GameEvent) {
+        if !self.streams.lock().unwrap().contains_key(&game.id) {
+            return;
+        }
+        // the message is made without holding the streams, since it reads from the db
+        let message = match event
+            .data(self, game)
+            .and_then(|data| event_message(event.name(), &data))
+        {
+            Ok(message) => message,
+            Err(err) => {
+                eprintln!(
+                    "sending {} event for game {} failed: {}",
+                    event.name(),
+                    game.id.0,
+                    ErrorResp::from(err).error
+                );
+                return;
+            }
+        };
+
+        let mut streams = self.streams.lock().unwrap();
+        if let Some(senders) = streams.get_mut(&game.id) {
+            // clients that have disconnected are forgotten
+            senders.retain(|sender| sender.send(message.clone()).is_ok());
+            if senders.is_empty() || event.ends_game() {
+                streams.remove(&game.id);
+            }
+        }
+    }
+
+    /// start streaming a game's events, in the given place in the limits on streams.
+    /// The first event is the game's current state
+    fn stream_events(&self, game_id: GameId, slot: StreamSlot) -> Result<GameEventStream, Error> {
+        let (sender, receiver) = mpsc::channel();
+        // events are sent while the game (or the cache, for games that aren't cached) is locked, so adding the stream
+        // while it is locked means the stream gets every change made after the state, and none made before it
+        let message = loop {
+            if let Some(cached) = self.cached_game(game_id) {
+                let game = cached.lock().unwrap();
+                // the game may have been removed from the cache while waiting for its lock
+                if !self.is_cached(game_id, &cached) {
+                    continue;
+                }
+                break self.add_stream(&game, sender)?;
+            } else {
+                let manager = self.manager.write().unwrap();
+                if manager.active_games.contains_key(&game_id) {
+                    continue;
+                }
+                break self.add_stream(&self.load_game_from_db(game_id)?, sender)?;
+            }
+        };
+
+        Ok(MessageReader::new(GameEvents {
+            first: Some(message),
+            events: receiver,
+            _slot: slot,
+        }))
+    }
+
+    /// add a stream of a game's events, returning the game's state as its first message.
+    /// streams of games that ended only have the game's state
+    fn add_stream(&self, game: &GameInstance, sender: Sender<String>) -> Result<String, Error> {
+        let message = event_message("state", &GameEvent::State.data(self, game)?)?;
+        if game.status() != GameStatus::Finished {
+            self.streams
+                .lock()
+                .unwrap()
+                .entry(game.id)
+                .or_default()
+                .push(sender);
+        }
+
+        Ok(message)
+    }
+
+    /// wake requests waiting for a game to change
+    fn game_saved(&self, game_id: GameId) {
+        if let Some(watch) = self.watches.lock().unwrap().get(&game_id) {
+            *watch.saves.lock().unwrap() += 1;
+            watch.saved.notify_all();
+        }
+    }
+
+    /// send a game to the connected bots of the players it is waiting on
+    fn notify_bots(&self, game: &GameInstance) {
+        let bots = self.bots.lock().unwrap();
+        for (seat, player) in game.players.iter().enumerate() {
+            if !game.waiting_on(seat as GamePlayer) {
+                continue;
+            }
+            if let Some(senders) = bots.players.get(player) {
+                for (_, sender) in senders {
+                    // a bot that is disconnecting removes itself once it is done
+                    let _ = sender.send(BotEvent::Turn(game.id));
+                }
+            }
+        }
+    }
+
+    /// post a game to the webhooks of the players it is waiting on, in the same form as the turns sent to bot websockets.
+    /// failures are logged, since the change is already saved
+    fn send_webhooks(&self, game: &GameInstance) {
+        for (seat, player) in game.players.iter().enumerate() {
+            if !game.waiting_on(seat as GamePlayer) {
+                continue;
+            }
+            if let Err(err) = self.send_webhook(game, *player) {
+                eprintln!(
+                    "sending webhook for game {} to user {} failed: {}",
+                    game.id.0,
+                    player.id(),
+                    ErrorResp::from(err).error
+                );
+            }
+        }
+    }
+
+    /// queue a game to be posted to a player's webhook, if they have one
+    fn send_webhook(&self, game: &GameInstance, player: PlayerId) -> Result<(), Error> {
+        use crate::schema::users;
+
+        let webhook = users::dsl::users
+            .find(player.id())
+            .select((users::dsl::webhook_url, users::dsl::webhook_secret))
+            .first::<(Option<String>, Option<String>)>(&*self.db)?;
+        if let (Some(url), Some(secret)) = webhook {
+            let turn = BotMessage::Turn {
+                game_id: game.id.0,
+                version: game.version,
+                game: Box::new(game_resp(self, game, player.id())?),
+            };
+            let webhook = Webhook {
+                url,
+                secret,
+                body: serde_json::to_string(&turn)?,
+            };
+            if let Err(TrySendError::Full(webhook)) = self.webhooks.try_send(webhook) {
+                eprintln!("webhook queue is full, dropping webhook to {}", webhook.url);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// make a move in a game as the given player, and return the game after the move.
+    /// if ply is given, the move is rejected unless it is the game's next move.
+    /// if the player already made the move with the same idempotency key, it isn't made again, and the game after it was made is returned
+    fn make_move(
+        &self,
+        game_id: GameId,
+        player: PlayerId,
+        player_move: &str,
+        ply: Option<i32>,
+        idempotency_key: Option<&str>,
+    ) -> Result<GameInstance, Error> {
+        let mut game = self.get_game(game_id)?;
+        if let Some(key) = idempotency_key {
+            let seat = game.get_player_index(player)?;
+            if let Some(made) = self.find_retried_move(&game, seat, key, player_move)? {
+                return Ok(made);
+            }
+        }
+        // checked first, so a retried move that was already made isn't reported as out of turn
+        if ply.map_or(false, |ply| ply != game.moves + 1) {
+            return Err(Error::StalePly);
+        }
+        if !game.active() {
+            return Err(Error::WrongTurn);
+        }
+        let player_index = game.get_player_index(player)?;
+        let game_int = game.game.as_mut().ok_or(Error::GameNotStarted)?;
+        if !game_int.waiting_on(player_index) {
+            return Err(Error::WrongTurn);
+        }
+        if !game_int.make_move(player_index, player_move)? {
+            return Err(Error::InvalidMove);
+        }
+        game.moves += 1;
+        match (
+            self.save_move(game.clone(), player_index, player_move, idempotency_key),
+            idempotency_key,
+        ) {
+            // a retry of the move with the same key may have been saved first
+            (Err(Error::GameChanged), Some(key)) => self
+                .find_retried_move(&game, player_index, key, player_move)?
+                .ok_or(Error::GameChanged),
+            (res, _) => res.map(|_| game),
+        }
+    }
+
+    /// if the player already made the move with the given idempotency key, get the game right after it.
+    /// a key can't be reused for a different move
+    fn find_retried_move(
+        &self,
+        game: &GameInstance,
+        seat: GamePlayer,
+        key: &str,
+        player_move: &str,
+    ) -> Result<Option<GameInstance>, Error> {
+        match self.find_move_by_key(game.id, seat, key)? {
+            Some(made) if made.player_move != player_move => Err(Error::IdempotencyKeyReused),
+            Some(made) => Ok(Some(self.game_after_move(game, made.ply)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// find the move a player made in a game with the given idempotency key
+    fn find_move_by_key(
+        &self,
+        game_id: GameId,
+        seat: GamePlayer,
+        key: &str,
+    ) -> Result<Option<DbGameMove>, Error> {
+        use crate::schema::game_moves;
+
+        Ok(game_moves::dsl::game_moves
+            .filter(game_moves::dsl::game_id.eq(game_id.0))
+            .filter(game_moves::dsl::seat.eq(seat as i32))
+            .filter(game_moves::dsl::idempotency_key.eq(key))
+            .first::<DbGameMove>(&*self.db)
+            .optional()?)
+    }
+
+    /// get a game as it was right after the move at the given ply, by replaying its moves
+    fn game_after_move(&self, game: &GameInstance, ply: i32) -> Result<GameInstance, Error> {
+        let mut after = game.clone();
+        after.game = Some(self.game_at_ply(game, ply)?);
+        after.moves = ply;
+
+        Ok(after)
+    }
+
+    /// get the active games that are waiting on a move from a player
+    fn games_awaiting_move(&self, player: PlayerId) -> Result<Vec<GameInstance>, Error> {
+        use crate::schema::db_games;
+        use crate::schema::game_players;
+
+        let game_ids = game_players::dsl::game_players
+            .inner_join(db_games::table)
+            .filter(game_players::dsl::user_id.eq(player.id()))
+            .filter(db_games::dsl::status.eq(GameStatus::Running.as_str()))
+            .select(db_games::dsl::id)
+            .order(db_games::dsl::id.asc())
+            .load::<i32>(&*self.db)?;
+
+        // running games are in the cache, which has their latest moves
+        let mut games = vec![];
+        for game_id in game_ids {
+            let game = self.get_game(GameId(game_id))?;
+            if game.waiting_on(game.get_player_index(player)?) {
+                games.push(game);
+            }
+        }
+
+        Ok(games)
+    }
+
+    /// save a game after a move was made in it by the player in the given seat, and add the move to the game's move log.
+    /// the cache and clients are only updated once both are committed
+    fn save_move(
+        &self,
+        game: GameInstance,
+        seat: GamePlayer,
+        player_move: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<(), Error> {
+        use crate::schema::game_moves;
+
+        // think time is measured from the previous move, or the start of the game
+        let think_time_ms = game
+            .last_move_at
+            .or(game.started_at)
+            .and_then(|since| SystemTime::now().duration_since(since).ok())
+            .map(|time| time.as_millis() as i64);
+        let new_move = NewDbGameMove {
+            game_id: game.id.id(),
+            ply: game.moves,
+            seat: seat as i32,
+            player_move,
+            think_time_ms,
+            idempotency_key,
+        };
+
+        // the move is logged in the same transaction as the game is saved, so clients only see moves that are in the log
+        self.save_game_with(game, GameEvent::Move { seat, player_move }, &|_| {
+            // another request already made a move at the same ply (or with the same idempotency key)
+            match diesel::insert_into(game_moves::table)
+                .values(&new_move)
+                .execute(&*self.db)
+            {
+                Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                    Err(Error::GameChanged)
+                }
+                res => res.map(|_| ()).map_err(Error::from),
+            }
+        })
+    }
+
+    /// get the move log of a game, in the order the moves were made
+    fn game_moves(&self, game_id: GameId) -> Result<Vec<DbGameMove>, Error> {
+        use crate::schema::game_moves;
+
+        Ok(game_moves::dsl::game_moves
+            .filter(game_moves::dsl::game_id.eq(game_id.id()))
+            .order(game_moves::dsl::ply.asc())
+            .load::<DbGameMove>(&*self.db)?)
+    }
+
+    /// reconstruct a game after its first ply moves by replaying its move log
+    fn game_at_ply(&self, game: &GameInstance, ply: i32) -> Result<Box<dyn AnyGame>, Error> {
+        if !game.started() {
+            return Err(Error::GameNotStarted);
+        }
+        if ply < 0 || ply > game.moves {
+            return Err(Error::InvalidPly);
+        }
+        let moves = self.game_moves(game.id)?;
+        if moves.len() != game.moves as usize {
+            return Err(Error::MissingMoveLog);
+        }
+
+        let kind = self.kinds.get(game.game_type)?;
+        replay_moves(kind, game.players.len(), &moves[..ply as usize])
+    }
+
+    /// called once a game has finished and been saved (with its players' new ratings)
+    fn game_finished(&self, game: &GameInstance) -> Result<(), Error> {
+        // the game is already saved, so a tournament that fails to advance is left for its owner to advance again
+        if let Some(tournament_id) = game.tournament {
+            if let Err(err) = self.advance_tournament(tournament_id) {
+                eprintln!(
+                    "tournament {} failed to advance after game {} finished: {}",
+                    tournament_id.id(),
+                    game.id.id(),
+                    ErrorResp::from(err).error
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// update the elo ratings of the players of a finished two player game
+    fn update_ratings(&self, game: &GameInstance) -> Result<(), Error> {
+        let players = match game.players[..] {
+            [first, second] => [first.id(), second.id()],
+            _ => return Ok(()),
+        };
+        let score = match game.game.as_ref().map(|g| g.outcome()) {
+            Some(GameOutcome::Win(0)) => 1.0,
+            Some(GameOutcome::Win(_)) => 0.0,
+            Some(GameOutcome::Tie) => 0.5,
+            _ => return Ok(()),
+        };
+
+        ratings::apply_elo(&*self.db, game.id.id(), players, score)
+    }
+
+    /// add a player to the given game
+    fn join_game(&self, game_id: GameId, player_id: PlayerId) -> Result<(), Error> {
+        let mut game = self.get_game(game_id)?;
+        if game.started() {
+            Err(Error::GameAlreadyStarted)
+        } else {
+            if game.players.contains(&player_id) {
+                Err(Error::AlreadyInGame)
+            } else {
+                game.players.push(player_id);
+                self.save_game(game, GameEvent::Join)?;
+
+                Ok(())
+            }
+        }
+    }
+
+    /// remove a player from the given game (if it has not started)
+    fn leave_game(&self, game_id: GameId, player_id: PlayerId) -> Result<(), Error> {
+        let mut game = self.get_game(game_id)?;
+
+        if !game.players.contains(&player_id) {
+            Err(Error::NotJoinedGame)
+        } else if game.started() {
+            Err(Error::GameAlreadyStarted)
+        } else {
+            game.players
+                .iter()
+                .position(|id| *id == player_id)
+                .map(|pos| game.players.remove(pos));
+            self.save_game(game, GameEvent::Leave)?;
+            Ok(())
+        }
+    }
+
+    /// start the game with the given id (ie -- give it a state)
+    /// player_id must be the owner of the game
+    fn start_game(&self, game_id: GameId, player_id: PlayerId) -> Result<(), Error> {
+        let mut game = self.get_game(game_id)?;
+
+        if game.owner != player_id {
+            Err(Error::NotGameOwner)
+        } else if game.started() {
+            Err(Error::GameAlreadyStarted)
+        } else {
+            let kind = self.kinds.get(game.game_type)?;
+            let num_players = game.players.len();
+            if kind.check_num_players(num_players) {
+                game.game = Some(kind.new_with_players(num_players));
+                self.save_game(game, GameEvent::Start)?;
+
+                Ok(())
+            } else {
+                Err(Error::InvalidNumPlayers)
+            }
+        }
+    }
+
+    /// get a list of all games ids in descending order
+    fn list_games(&self) -> Result<Vec<i32>, Error> {
+        use crate::schema::db_games;
+
+        let ids = db_games::dsl::db_games
+            .filter(db_games::dsl::is_public.eq(true))
+            .select(db_games::dsl::id)
+            .order(db_games::id.desc())
+            .load::<i32>(&*self.db)?;
+
+        Ok(ids)
+    }
+
+    /// count the games a player has played or joined
+    fn count_player_games(&self, player_id: PlayerId) -> Result<i64, Error> {
+        use crate::schema::game_players;
+
+        Ok(game_players::dsl::game_players
+            .filter(game_players::dsl::user_id.eq(player_id.id()))
+            .count()
+            .get_result::<i64>(&*self.db)?)
+    }
+
+    /// get a page of the games a player has played or joined, newest first.
+    /// games that can't be loaded are left out
+    fn player_games(
+        &self,
+        player_id: PlayerId,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<GameInstance>, Error> {
+        use crate::schema::db_games;
+        use crate::schema::game_players;
+
+        let entries = game_players::dsl::game_players
+            .inner_join(db_games::table)
+            .filter(game_players::dsl::user_id.eq(player_id.id()))
+            .select(db_games::all_columns)
+            .order(db_games::dsl::id.desc())
+            .offset(offset)
+            .limit(limit)
+            .load::<DbGame>(&*self.db)?;
+        let ids = entries.iter().map(|entry| entry.id).collect::<Vec<i32>>();
+        let mut players = load_game_players(&*self.db, ids)?;
+
+        let mut games = vec![];
+        for entry in entries {
+            let id = entry.id;
+            let game_players = players.remove(&id).unwrap_or_default();
+            match GameInstance::from_db(entry, game_players, &self.kinds) {
+                // running games may have newer moves in the cache
+                Ok(game) => games.push(match self.cached_game(game.id) {
+                    Some(cached) => cached.lock().unwrap().clone(),
+                    None => game,
+                }),
+                Err(err) => eprintln!(
+                    "game {} can't be loaded, leaving it off the user's games: {}",
+                    id,
+                    ErrorResp::from(err).error
+                ),
+            }
+        }
+
+        Ok(games)
+    }
+
+    /// get a player's record in finished games
+    fn player_record(&self, player_id: PlayerId) -> Result<Record, Error> {
+        use crate::schema::db_games;
+        use crate::schema::game_players;
+        use diesel::dsl::sql;
+        use diesel::sql_types::BigInt;
+
+        self.record_missing_outcomes()?;
+        // count the player's games by outcome
+        let counts = game_players::dsl::game_players
+            .inner_join(db_games::table)
+            .filter(game_players::dsl::user_id.eq(player_id.id()))
+            .filter(db_games::dsl::status.eq(GameStatus::Finished.as_str()))
+            .filter(game_players::dsl::outcome.is_not_null())
+            .group_by(game_players::dsl::outcome)
+            .select((game_players::dsl::outcome, sql::<BigInt>("count(*)")))
+            .load::<(Option<String>, i64)>(&*self.db)?;
+
+        let mut record = Record::default();
+        for (outcome, count) in counts {
+            record.add_outcomes(&outcome.unwrap_or_default(), count as u32);
+        }
+
+        Ok(record)
+    }
+
+    /// get the leaderboard of all users who played at least min_games games
+    fn leaderboard(&self, min_games: u32) -> Result<Vec<LeaderboardEntry>, Error> {
+        use crate::schema::db_games;
+        use crate::schema::game_players;
+        use crate::schema::users;
+        use diesel::dsl::sql;
+        use diesel::sql_types::BigInt;
+
+        self.record_missing_outcomes()?;
+        let users = users::dsl::users.load::<User>(&*self.db)?;
+        // count each player's games by outcome
+        let counts = game_players::dsl::game_players
+            .inner_join(db_games::table)
+            .filter(db_games::dsl::status.eq(GameStatus::Finished.as_str()))
+            .filter(game_players::dsl::outcome.is_not_null())
+            .group_by((game_players::dsl::user_id, game_players::dsl::outcome))
+            .select((
+                game_players::dsl::user_id,
+                game_players::dsl::outcome,
+                sql::<BigInt>("count(*)"),
+            ))
+            .load::<(i32, Option<String>, i64)>(&*self.db)?;
+
+        let mut records = HashMap::<i32, Record>::new();
+        for (player, outcome, count) in counts {
+            records
+                .entry(player)
+                .or_default()
+                .add_outcomes(&outcome.unwrap_or_default(), count as u32);
+        }
+
+        Ok(compute_leaderboard(users, records, min_games))
+    }
+
+    /// record the outcomes of finished games that don't have them (games that finished before outcomes were recorded).
+    /// games that can't be loaded are left off the leaderboard
+    fn record_missing_outcomes(&self) -> Result<(), Error> {
+        use crate::schema::db_games;
+        use crate::schema::game_players;
+
+        let ids = game_players::dsl::game_players
+            .inner_join(db_games::table)
+            .filter(db_games::dsl::status.eq(GameStatus::Finished.as_str()))
+            .filter(game_players::dsl::outcome.is_null())
+            .select(game_players::dsl::game_id)
+            .distinct()
+            .load::<i32>(&*self.db)?;
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let entries = db_games::dsl::db_games
+            .filter(db_games::dsl::id.eq_any(&ids))
+            .load::<DbGame>(&*self.db)?;
+        let mut players = load_game_players(&*self.db, ids)?;
+        for entry in entries {
+            let id = entry.id;
+            let game_players = players.remove(&id).unwrap_or_default();
+            match GameInstance::from_db(entry, game_players, &self.kinds).map(|game| game.result())
+            {
+                Ok(Some(result)) => write_outcomes(&*self.db, GameId(id), &result)?,
+                Ok(None) => eprintln!(
+                    "finished game {} has no result, leaving it off the leaderboard",
+                    id
+                ),
+                Err(err) => eprintln!(
+                    "finished game {} can't be loaded, leaving it off the leaderboard: {}",
+                    id,
+                    ErrorResp::from(err).error
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// get the display names of the given players
+    fn player_names(&self, players: &[PlayerId]) -> Result<Vec<String>, Error> {
+        use crate::schema::users;
+
+        players
+            .iter()
+            .map(|id| -> Result<String, Error> {
+                Ok(users::dsl::users
+                    .find(id.id())
+                    .first::<User>(&*self.db)?
+                    .display_name)
+            })
+            .collect::<Result<Vec<String>, Error>>()
+    }
+
+    /// create a new tournament playing the given kind of game.
+    /// rounds is the number of rounds to play in a swiss tournament (if None, it is picked from the number of players)
+    /// best_of is the number of games in each series of an elimination tournament
+    fn new_tournament(
+        &self,
+        name: &str,
+        owner: PlayerId,
+        game_type: &str,
+        format: TournamentFormat,
+        rounds: Option<i32>,
+        best_of: i32,
+    ) -> Result<TournamentId, Error> {
+        use crate::schema::tournaments;
+
+        let kind = self.kinds.get(game_type)?;
+        if rounds.map_or(false, |rounds| rounds < 1) {
+            return Err(Error::InvalidNumRounds);
+        }
+        if best_of < 1 || best_of % 2 == 0 {
+            return Err(Error::InvalidSeriesLength);
+        }
+        let tournament = NewTournament {
+            owner_id: owner.id(),
+            name,
+            players: vec![],
+            games: None,
+            game_type: kind.id,
+            format: format.as_str(),
+            rounds,
+            best_of,
+        };
+
+        let inserted = diesel::insert_into(tournaments::table)
+            .values(&tournament)
+            .get_result::<Tournament>(&*self.db)?;
+
+        Ok(TournamentId(inserted.id))
+    }
+
+    /// find a tournament from db
+    fn get_tournament(&self, id: TournamentId) -> Result<Tournament, Error> {
+        use crate::schema::tournaments;
+
+        tournaments::dsl::tournaments
+            .find(id.0)
+            .first::<Tournament>(&*self.db)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => Error::InvalidTournamentId,
+                e => Error::DBError(e),
+            })
+    }
+
+    /// get the results of all finished games in a tournament
+    fn tournament_results(&self, tournament: &Tournament) -> Result<Vec<GameResult>, Error> {
+        let mut results = vec![];
+        for game_id in tournament.games.iter().flatten() {
+            if let Some(result) = self.get_game(GameId(*game_id))?.result() {
+                results.push(result);
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// get a list of all tournament ids in descending order
+    fn list_tournaments(&self) -> Result<Vec<i32>, Error> {
+        use crate::schema::tournaments;
+
+        let ids = tournaments::dsl::tournaments
+            .select(tournaments::dsl::id)
+            .order(tournaments::dsl::id.desc())
+            .load::<i32>(&*self.db)?;
+
+        Ok(ids)
+    }
+
+    /// save a tournament to db
+    fn save_tournament(&self, tournament: &Tournament) -> Result<(), Error> {
+        use crate::schema::tournaments;
+
+        diesel::update(tournaments::dsl::tournaments.find(tournament.id))
+            .set(tournament)
+            .execute(&*self.db)?;
+        Ok(())
+    }
+
+    /// join a tournament
+    fn join_tournament(
+        &self,
+        tournament_id: TournamentId,
+        player_id: PlayerId,
+    ) -> Result<(), Error> {
+        let mut tournament = self.get_tournament(tournament_id)?;
+
+        if tournament.games.is_some() {
+            Err(Error::GameAlreadyStarted)
+        } else {
+            if tournament.players.contains(&player_id.id()) {
+                Err(Error::AlreadyInGame)
+            } else {
+                tournament.players.push(player_id.id());
+                self.save_tournament(&tournament)?;
+
+                Ok(())
+            }
+        }
+    }
+
+    /// leave a tournament
+    fn leave_tournament(&self, id: TournamentId, player_id: PlayerId) -> Result<(), Error> {
+        let mut tournament = self.get_tournament(id)?;
+
+        if tournament.games.is_some() {
+            Err(Error::GameAlreadyStarted)
+        } else {
+            if !tournament.players.contains(&player_id.id()) {
+                Err(Error::NotJoinedGame)
+            } else {
+                tournament
+                    .players
+                    .iter()
+                    .position(|id| *id == player_id.id())
+                    .map(|pos| tournament.players.remove(pos));
+                self.save_tournament(&tournament)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// start a tournament and generate match schedule
+    fn start_tournament(&self, id: TournamentId, player_id: PlayerId) -> Result<(), Error> {
+        let mut tournament = self.get_tournament(id)?;
+
+        if tournament.owner_id != player_id.id() {
+            Err(Error::NotGameOwner)
+        } else if tournament.games.is_some() {
+            Err(Error::GameAlreadyStarted)
+        } else if tournament.players.len() < TOURNAMENT_GAME_PLAYERS {
+            Err(Error::InvalidNumPlayers)
+        } else {
+            let format = tournament.format.parse::<TournamentFormat>()?;
+            self.db.transaction::<_, Error, _>(|| match format {
+                TournamentFormat::RoundRobin => self.start_round_robin(&mut tournament),
+                TournamentFormat::Swiss => {
+                    if tournament.rounds.is_none() {
+                        tournament.rounds = Some(swiss::default_rounds(tournament.players.len()));
+                    }
+                    self.start_swiss_round(&mut tournament)
+                }
+                TournamentFormat::SingleElimination | TournamentFormat::DoubleElimination => {
+                    let seeds = tournament.players.clone();
+                    let best_of = tournament.best_of as u32;
+                    let bracket = if format == TournamentFormat::SingleElimination {
+                        Bracket::single_elimination(seeds, best_of)
+                    } else {
+                        Bracket::double_elimination(seeds, best_of)
+                    };
+                    tournament.current_round = 1;
+                    self.advance_bracket(&mut tournament, bracket)
+                }
+            })
+        }
+    }
+
+    /// create and start the games of a round-robin tournament
+    /// every combination of players plays one game
+    fn start_round_robin(&self, tournament: &mut Tournament) -> Result<(), Error> {
+        let owner = PlayerId::new(tournament.owner_id);
+        let mut games = vec![];
+        for match_indices in (0..tournament.players.len()).combinations(TOURNAMENT_GAME_PLAYERS) {
+            // rotate the seating order so that each player moves first in about the same number of games
+            let mut match_players = match_indices
+                .iter()
+                .map(|index| PlayerId::new(tournament.players[*index]))
+                .collect::<Vec<PlayerId>>();
+            let rotation = match_indices.iter().sum::<usize>() % match_players.len();
+            match_players.rotate_left(rotation);
+
+            let name = format!("{}: game {}", tournament.name, games.len() + 1);
+            let game_id = self.new_started_game(
+                &name,
+                owner,
+                &tournament.game_type,
+                &match_players,
+                Some(TournamentId(tournament.id)),
+            )?;
+            games.push(game_id.id());
+        }
+
+        tournament.games = Some(games);
+        tournament.current_round = 1;
+        self.save_tournament(tournament)
+    }
+
+    /// pair players for the next round of a swiss tournament from the current standings, and start its games
+    /// swiss tournaments are only supported for two player games
+    fn start_swiss_round(&self, tournament: &mut Tournament) -> Result<(), Error> {
+        let mut games = tournament.games.clone().unwrap_or_default();
+
+        let mut played = HashSet::new();
+        let mut first_balance = HashMap::<i32, i32>::new();
+        let mut results = vec![];
+        for game_id in &games {
+            let game = self.load_game_from_db(GameId(*game_id))?;
+            if let [first, second] = game.players[..] {
+                played.insert((first.id(), second.id()));
+                *first_balance.entry(first.id()).or_insert(0) += 1;
+                *first_balance.entry(second.id()).or_insert(0) -= 1;
+            }
+            if let Some(result) = game.result() {
+                results.push(result);
+            }
+        }
+
+        let standings = compute_standings(&tournament.players, &results, &tournament.byes);
+        let scores = standings
+            .standings
+            .iter()
+            .map(|standing| (standing.player_id, standing.score))
+            .collect::<HashMap<i32, f64>>();
+        let players = tournament
+            .players
+            .iter()
+            .map(|id| SwissPlayer {
+                id: *id,
+                score: scores[id],
+                first_balance: first_balance.get(id).cloned().unwrap_or(0),
+                had_bye: tournament.byes.contains(id),
+            })
+            .collect::<Vec<SwissPlayer>>();
+
+        let round = swiss::pair_round(&players, &played);
+        tournament.current_round += 1;
+        let owner = PlayerId::new(tournament.owner_id);
+        for (i, (first, second)) in round.pairs.iter().enumerate() {
+            let name = format!(
+                "{}: round {} game {}",
+                tournament.name,
+                tournament.current_round,
+                i + 1
+            );
+            let game_id = self.new_started_game(
+                &name,
+                owner,
+                &tournament.game_type,
+                &[PlayerId::new(*first), PlayerId::new(*second)],
+                Some(TournamentId(tournament.id)),
+            )?;
+            games.push(game_id.id());
+        }
+        if let Some(bye) = round.bye {
+            tournament.byes.push(bye);
+        }
+
+        tournament.games = Some(games);
+        self.save_tournament(tournament)
+    }
+
+    /// update an elimination tournament's bracket from the results of its games, and start any games that are needed
+    fn advance_bracket(
+        &self,
+        tournament: &mut Tournament,
+        mut bracket: Bracket,
+    ) -> Result<(), Error> {
+        let mut results = HashMap::new();
+        // games are read from the db, which has every game that finished before the tournament was locked
+        for game_id in bracket.matches.iter().flat_map(|m| m.games.iter()) {
+            let game = self.load_game_from_db(GameId(*game_id))?;
+            let result = match game.game.as_ref().map(|g| g.outcome()) {
+                _ if game.active() => SeriesGame::Pending,
+                Some(GameOutcome::Win(player)) => {
+                    SeriesGame::Won(game.players[player as usize].id())
+                }
+                _ => SeriesGame::Tied,
+            };
+            results.insert(*game_id, result);
+        }
+
+        let to_start = bracket.advance(&|game_id| {
+            results
+                .get(&game_id)
+                .cloned()
+                .unwrap_or(SeriesGame::Pending)
+        });
+
+        let mut games = tournament.games.clone().unwrap_or_default();
+        let owner = PlayerId::new(tournament.owner_id);
+        for (match_index, [first, second]) in to_start {
+            let m = &bracket.matches[match_index];
+            let name = format!(
+                "{}: {:?} round {} game {}",
+                tournament.name,
+                m.side,
+                m.round,
+                m.games.len() + 1
+            );
+            let game_id = self.new_started_game(
+                &name,
+                owner,
+                &tournament.game_type,
+                &[PlayerId::new(first), PlayerId::new(second)],
+                Some(TournamentId(tournament.id)),
+            )?;
+            bracket.add_game(match_index, game_id.id());
+            games.push(game_id.id());
+        }
+
+        tournament.games = Some(games);
+        tournament.bracket = Some(serde_json::to_string(&bracket)?);
+        self.save_tournament(tournament)
+    }
+
+    /// advance a started tournament again (if advancing it failed when one of its games finished).
+    /// user must be the owner of the tournament or an admin
+    fn readvance_tournament(&self, id: TournamentId, user: &User) -> Result<(), Error> {
+        let tournament = self.get_tournament(id)?;
+
+        if tournament.owner_id != user.id && !user.is_admin {
+            Err(Error::NotGameOwner)
+        } else if tournament.games.is_none() {
+            Err(Error::GameNotStarted)
+        } else {
+            self.advance_tournament(id)
+        }
+    }
+
+    /// move a tournament on after one of its games finished
+    /// (in a swiss tournament, the next round starts once every game in the current round is finished,
+    /// and in an elimination tournament, the winners of series advance).
+    /// must be called after the game's transaction commits, so that when games finish at once, the last to lock the
+    /// tournament sees all of them finished
+    fn advance_tournament(&self, id: TournamentId) -> Result<(), Error> {
+        use crate::schema::tournaments;
+
+        self.db.transaction::<_, Error, _>(|| {
+            // lock the tournament so that games finishing at the same time don't both advance it
+            let mut tournament = tournaments::dsl::tournaments
+                .find(id.id())
+                .for_update()
+                .first::<Tournament>(&*self.db)?;
+
+            match tournament.format.parse::<TournamentFormat>()? {
+                TournamentFormat::RoundRobin => Ok(()),
+                TournamentFormat::Swiss => {
+                    if tournament.current_round >= tournament.rounds.unwrap_or(0) {
+                        return Ok(());
+                    }
+                    // games are read from the db, which has every game that finished before the tournament was locked
+                    for game_id in tournament.games.iter().flatten() {
+                        if self.load_game_from_db(GameId(*game_id))?.active() {
+                            return Ok(());
+                        }
+                    }
+
+                    self.start_swiss_round(&mut tournament)
+                }
+                TournamentFormat::SingleElimination | TournamentFormat::DoubleElimination => {
+                    let bracket = tournament
+                        .bracket
+                        .as_ref()
+                        .map_or(Err(Error::NoBracket), |b| Ok(serde_json::from_str(b)?))?;
+                    self.advance_bracket(&mut tournament, bracket)
+                }
+            }
+        })
+    }
+}
+
+pub type AppReqState<'a> = State<'a, GameServer>;
+
+#[derive(Serialize, Debug)]
+pub struct GameResp {
+    name: String,
+    game_type: String,
+    rated: bool,
+    owner_id: i32,
+    state: Option<serde_json::Value>,
+    players: Vec<String>,
+    player_ids: Vec<i32>,
+    active: bool,
+    started: bool,
+    waiting_on: Vec<bool>,
+    outcome: String,
+    moves: i32,
+    /// "lobby", "running", or "finished"
+    status: &'static str,
+    /// timestamps are in seconds since the unix epoch
+    created_at: u64,
+    started_at: Option<u64>,
+    finished_at: Option<u64>,
+    last_move_at: Option<u64>,
+}
+
+/// describe a game, with its state as seen by the given player
+fn game_resp(app: &AppState, game: &GameInstance, player_id: i32) -> Result<GameResp, Error> {
+    let players = app.player_names(&game.players)?;
+
+    let player_ids = game.players.iter().map(|id| id.id()).collect::<Vec<i32>>();
+
+    let waiting_on = if game.active() {
+        game.players
+            .iter()
+            .enumerate()
+            .map(|(index, _)| {
+                game.game
+                    .as_ref()
+                    .map_or(false, |g| g.waiting_on(index as u32))
+            })
+            .collect::<Vec<bool>>()
+    } else {
+        Vec::<bool>::new()
+    };
+
+    let game_player_display_for = player_ids
+        .iter()
+        .position(|id| *id == player_id)
+        .map_or(0, |index| index);
+
+    let outcome = game.outcome_message(&players);
+
+    let state = match &game.game {
+        Some(g) => Some(g.state(game_player_display_for as u32)?),
+        None => None,
+    };
+
+    let status = game.status().as_str();
+
+    Ok(GameResp {
+        game_type: game.game_type.to_string(),
+        rated: game.rated,
+        owner_id: game.owner.id(),
+        state,
+        players,
+        player_ids,
+        active: game.active(),
+        started: game.started(),
+        waiting_on,
+        name: game.name.clone(),
+        outcome,
+        moves: game.moves,
+        status,
+        created_at: unix_time(game.created_at),
+        started_at: game.started_at.map(unix_time),
+        finished_at: game.finished_at.map(unix_time),
+        last_move_at: game.last_move_at.map(unix_time),
+    })
+}
+
+fn game_get_internal(
+    player_id: i32,
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    if_none_match: IfNoneMatch,
+) -> Result<Tagged<Json<GameResp>>, Error> {
+    let app = AppState::new(db, &*state);
+
+    let game = app.get_game(GameId(id))?;
+    // the state is described differently to each player
+    let etag = format!("W/\"{}-{}\"", game.version, player_id);
+    if if_none_match.matches(&etag) {
+        return Ok(Tagged::NotModified(etag));
+    }
+
+    Ok(Tagged::Modified(
+        etag,
+        Json(game_resp(&app, &game, player_id)?),
+    ))
+}
+
+#[get("/game/<id>?<dont_invert>")]
+pub fn game_get_user_authd(
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    user: ForwardingUser,
+    dont_invert: Option<bool>,
+    if_none_match: IfNoneMatch,
+) -> Result<Tagged<Json<GameResp>>, Error> {
+    let player_id = match dont_invert {
+        None | Some(false) => user.0.id,
+        Some(true) => 0,
+    };
+    game_get_internal(player_id, id, db, state, if_none_match)
+}
+
+#[get("/game/<id>?<dont_invert>", rank = 2)]
+pub fn game_get(
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    dont_invert: Option<bool>,
+    if_none_match: IfNoneMatch,
+) -> Result<Tagged<Json<GameResp>>, Error> {
+    game_get_internal(0, id, db, state, if_none_match)
+}
+
+#[derive(Serialize)]
+pub struct NeededResp {
+    needed: bool,
+}
+
+#[get("/game/<id>/move_needed")]
+pub fn game_move_needed(
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<NeededResp>, Error> {
+    let app = AppState::new(db, &*state);
+    let game = app.get_game(GameId(id))?;
+    if !game.active() {
+        Ok(Json(NeededResp { needed: false }))
+    } else {
+        let player_index = game.get_player_index(PlayerId::new(user.id))?;
+        let needed = game
+            .game
+            .as_ref()
+            .map_or(false, |game| game.waiting_on(player_index));
+        Ok(Json(NeededResp { needed }))
+    }
+}
+
+#[derive(Serialize)]
+pub struct AwaitingGameResp {
+    id: i32,
+    #[serde(flatten)]
+    game: GameResp,
+}
+
+#[derive(Serialize)]
+pub struct AwaitingMoveResp {
+    games: Vec<AwaitingGameResp>,
+}
+
+#[get("/games/awaiting_move")]
+pub fn games_awaiting_move(
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<AwaitingMoveResp>, Error> {
+    let app = AppState::new(db, &*state);
+    let games = app
+        .games_awaiting_move(PlayerId::new(user.id))?
+        .iter()
+        .map(|game| {
+            Ok(AwaitingGameResp {
+                id: game.id.0,
+                game: game_resp(&app, game, user.id)?,
+            })
+        })
+        .collect::<Result<Vec<AwaitingGameResp>, Error>>()?;
+
+    Ok(Json(AwaitingMoveResp { games }))
+}
+
+#[derive(Serialize)]
+pub struct EventResp {
+    /// the seat of the player who moved and their move, for move events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seat: Option<GamePlayer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    player_move: Option<String>,
+    game: GameResp,
+}
+
+#[get("/game/<id>/events")]
+pub fn game_events(
+    id: i32,
+    db: DBConn,
+    client: ClientIp,
+    state: AppReqState,
+) -> Result<Content<Stream<GameEventStream>>, Error> {
+    // each stream holds a worker thread until its game ends, so the number open is limited
+    let slot = state.open_stream(client.0)?;
+    let app = AppState::new(db, &*state);
+    let events = app.stream_events(GameId(id), slot)?;
+
+    Ok(Content(
+        ContentType::new("text", "event-stream"),
+        Stream::chunked(events, EVENT_CHUNK_SIZE),
+    ))
+}
+
+/// default and maximum number of seconds to wait in /game/<id>/wait
+const WAIT_TIMEOUT: u64 = 30;
+const MAX_WAIT_TIMEOUT: u64 = 60;
+/// maximum number of requests a player can have waiting for their turn at once
+const MAX_WAITS_PER_PLAYER: usize = 4;
+
+#[derive(Serialize)]
+pub struct WaitResp {
+    needed: bool,
+    #[serde(flatten)]
+    game: GameResp,
+}
+
+#[get("/game/<id>/wait?<timeout>")]
+pub fn game_wait(
+    id: i32,
+    timeout: Option<u64>,
+    db: LazyDBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<WaitResp>, Error> {
+    let timeout = timeout.unwrap_or(WAIT_TIMEOUT);
+    if timeout > MAX_WAIT_TIMEOUT {
+        return Err(Error::InvalidTimeout);
+    }
+    let (game, needed) = state.wait_for_turn(
+        &db,
+        GameId(id),
+        PlayerId::new(user.id),
+        Duration::from_secs(timeout),
+    )?;
+
+    let app = AppState::new(db.get()?, &*state);
+    Ok(Json(WaitResp {
+        needed,
+        game: game_resp(&app, &game, user.id)?,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct MadeMoveResp {
+    success: bool,
+    ply: i32,
+    /// the game's state (as seen by the player who moved) and outcome after the move
+    state: serde_json::Value,
+    finished: bool,
+    outcome: String,
+}
+
+#[post("/game/<id>/move?<ply>&<idempotency_key>", data = "<player_move>")]
+pub fn game_move(
+    id: i32,
+    ply: Option<i32>,
+    idempotency_key: Option<String>,
+    player_move: String,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<MadeMoveResp>, Error> {
+    let app = AppState::new(db, &*state);
+    let player = PlayerId::new(user.id);
+    let game = app.make_move(
+        GameId(id),
+        player,
+        &player_move,
+        ply,
+        idempotency_key.as_deref(),
+    )?;
+    let seat = game.get_player_index(player)?;
+    let state = game
+        .game
+        .as_ref()
+        .ok_or(Error::GameNotStarted)?
+        .state(seat)?;
+
+    Ok(Json(MadeMoveResp {
+        success: true,
+        ply: game.moves,
+        state,
+        finished: game.status() == GameStatus::Finished,
+        outcome: game.outcome_message(&app.player_names(&game.players)?),
+    }))
+}
+
+#[derive(Serialize)]
+pub struct MoveResp {
+    ply: i32,
+    seat: i32,
+    player_id: i32,
+    /// the move, as it was sent to `/game/<id>/move`
+    player_move: String,
+    /// seconds since the unix epoch
+    created_at: u64,
+    /// time since the previous move (or the start of the game) in milliseconds
+    think_time_ms: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct MovesResp {
+    moves: Vec<MoveResp>,
+}
+
+#[get("/game/<id>/moves")]
+pub fn game_moves(id: i32, db: DBConn, state: AppReqState) -> Result<Json<MovesResp>, Error> {
+    let app = AppState::new(db, &*state);
+    let game = app.get_game(GameId(id))?;
+    let moves = app
+        .game_moves(GameId(id))?
+        .into_iter()
+        .map(|game_move| MoveResp {
+            ply: game_move.ply,
+            seat: game_move.seat,
+            player_id: game
+                .players
+                .get(game_move.seat as usize)
+                .map_or(0, |player| player.id()),
+            player_move: game_move.player_move,
+            created_at: unix_time(game_move.created_at),
+            think_time_ms: game_move.think_time_ms,
+        })
+        .collect();
+
+    Ok(Json(MovesResp { moves }))
+}
+
+#[derive(Serialize)]
+pub struct StateAtResp {
+    ply: i32,
+    state: serde_json::Value,
+    finished: bool,
+}
+
+/// state of a game after its first ply moves (as seen by the first player)
+#[get("/game/<id>/state_at/<ply>")]
+pub fn game_state_at(
+    id: i32,
+    ply: i32,
+    db: DBConn,
+    state: AppReqState,
+) -> Result<Json<StateAtResp>, Error> {
+    let app = AppState::new(db, &*state);
+    let game = app.get_game(GameId(id))?;
+    let replayed = app.game_at_ply(&game, ply)?;
+
+    Ok(Json(StateAtResp {
+        ply,
+        state: replayed.state(0)?,
+        finished: replayed.finished(),
+    }))
+}
+
+#[derive(FromForm)]
+pub struct NewGameForm {
+    name: String,
+    game_type: Option<String>,
+    rated: Option<bool>,
+}
+
+#[post("/game/new", data = "<new_game>")]
+pub fn game_new(
+    new_game: Form<NewGameForm>,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<IdResp>, Error> {
+    let app = AppState::new(db, &*state);
+    let game_type = new_game
+        .game_type
+        .as_ref()
+        .map_or(crate::DEFAULT_GAME_TYPE, |t| t.as_str());
+    let id = app.new_game(
+        &new_game.name,
+        PlayerId::new(user.id),
+        game_type,
+        new_game.rated.unwrap_or(false),
+    )?;
+
+    Ok(Json(IdResp { id: id.to_string() }))
+}
+
+#[post("/game/<id>/join")]
+pub fn game_join(
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<SuccessResp>, Error> {
     let app = AppState::new(db, &*state);
     app.join_game(GameId(id), PlayerId::new(user.id))?;
     Ok(Json(SuccessResp { success: true }))
 }
 
-#[post("/game/<id>/leave")]
-pub fn game_leave(
+#[post("/game/<id>/leave")]
+pub fn game_leave(
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<SuccessResp>, Error> {
+    let app = AppState::new(db, &*state);
+    app.leave_game(GameId(id), PlayerId::new(user.id))?;
+    Ok(Json(SuccessResp { success: true }))
+}
+
+#[post("/game/<id>/start")]
+pub fn game_start(
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<SuccessResp>, Error> {
+    let app = AppState::new(db, &*state);
+    app.start_game(GameId(id), PlayerId::new(user.id))?;
+    Ok(Json(SuccessResp { success: true }))
+}
+
+#[derive(Serialize)]
+pub struct IndexResp {
+    games: Vec<i32>,
+}
+
+#[get("/game/index")]
+pub fn game_index(db: DBConn, state: AppReqState) -> Result<Json<IndexResp>, Error> {
+    let app = AppState::new(db, &*state);
+    let games = app.list_games()?;
+
+    Ok(Json(IndexResp { games }))
+}
+
+#[derive(Serialize)]
+pub struct GameTypesResp {
+    types: Vec<&'static str>,
+}
+
+#[get("/game/types")]
+pub fn game_types(state: AppReqState) -> Json<GameTypesResp> {
+    Json(GameTypesResp {
+        types: state.kinds.ids(),
+    })
+}
+
+#[derive(FromForm)]
+pub struct NewTournamentForm {
+    name: String,
+    game_type: Option<String>,
+    format: Option<String>,
+    rounds: Option<i32>,
+    best_of: Option<i32>,
+}
+
+#[post("/tournament/new", data = "<new_tournament>")]
+pub fn tournament_new(
+    new_tournament: Form<NewTournamentForm>,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<IdResp>, Error> {
+    let app = AppState::new(db, &*state);
+    let game_type = new_tournament
+        .game_type
+        .as_ref()
+        .map_or(crate::DEFAULT_GAME_TYPE, |t| t.as_str());
+    let format = new_tournament
+        .format
+        .as_ref()
+        .map_or(Ok(TournamentFormat::RoundRobin), |f| f.parse())?;
+    let id = app.new_tournament(
+        &new_tournament.name,
+        PlayerId::new(user.id),
+        game_type,
+        format,
+        new_tournament.rounds,
+        new_tournament.best_of.unwrap_or(1),
+    )?;
+
+    Ok(Json(IdResp { id: id.to_string() }))
+}
+
+#[post("/tournament/<id>/join")]
+pub fn tournament_join(
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<SuccessResp>, Error> {
+    let app = AppState::new(db, &*state);
+    app.join_tournament(TournamentId(id), PlayerId::new(user.id))?;
+    Ok(Json(SuccessResp { success: true }))
+}
+
+#[post("/tournament/<id>/leave")]
+pub fn tournament_leave(
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<SuccessResp>, Error> {
+    let app = AppState::new(db, &*state);
+    app.leave_tournament(TournamentId(id), PlayerId::new(user.id))?;
+    Ok(Json(SuccessResp { success: true }))
+}
+
+#[post("/tournament/<id>/start")]
+pub fn tournament_start(
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<SuccessResp>, Error> {
+    let app = AppState::new(db, &*state);
+    app.start_tournament(TournamentId(id), PlayerId::new(user.id))?;
+    Ok(Json(SuccessResp { success: true }))
+}
+
+#[post("/tournament/<id>/advance")]
+pub fn tournament_advance(
+    id: i32,
+    db: DBConn,
+    state: AppReqState,
+    user: User,
+) -> Result<Json<SuccessResp>, Error> {
+    let app = AppState::new(db, &*state);
+    app.readvance_tournament(TournamentId(id), &user)?;
+    Ok(Json(SuccessResp { success: true }))
+}
+
+#[derive(Serialize, Debug)]
+pub struct TournamentGameResp {
+    id: i32,
+    player_ids: Vec<i32>,
+    active: bool,
+    outcome: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TournamentResp {
+    name: String,
+    game_type: String,
+    format: String,
+    current_round: i32,
+    rounds: Option<i32>,
+    best_of: i32,
+    owner_id: i32,
+    players: Vec<String>,
+    player_ids: Vec<i32>,
+    byes: Vec<i32>,
+    started: bool,
+    games: Vec<TournamentGameResp>,
+}
+
+#[get("/tournament/<id>")]
+pub fn tournament_get(
     id: i32,
     db: DBConn,
     state: AppReqState,
-    user: User,
-) -> Result<Json<SuccessResp>, Json<ErrorResp>> {
+) -> Result<Json<TournamentResp>, Error> {
     let app = AppState::new(db, &*state);
-    app.leave_game(GameId(id), PlayerId::new(user.id))?;
-    Ok(Json(SuccessResp { success: true }))
+    let tournament = app.get_tournament(TournamentId(id))?;
+
+    let player_ids = tournament.players.clone();
+    let players = app.player_names(
+        &player_ids
+            .iter()
+            .map(|id| PlayerId::new(*id))
+            .collect::<Vec<PlayerId>>(),
+    )?;
+    let names = player_ids
+        .iter()
+        .cloned()
+        .zip(players.iter().cloned())
+        .collect::<HashMap<i32, String>>();
+
+    let games = tournament
+        .games
+        .as_ref()
+        .map_or(vec![], |games| games.clone())
+        .into_iter()
+        .map(|game_id| -> Result<TournamentGameResp, Error> {
+            let game = app.get_game(GameId(game_id))?;
+            let game_players = game
+                .players
+                .iter()
+                .map(|id| names.get(&id.id()).cloned().unwrap_or_default())
+                .collect::<Vec<String>>();
+
+            Ok(TournamentGameResp {
+                id: game_id,
+                player_ids: game.players.iter().map(|id| id.id()).collect(),
+                active: game.active(),
+                outcome: game.outcome_message(&game_players),
+            })
+        })
+        .collect::<Result<Vec<TournamentGameResp>, Error>>()?;
+
+    Ok(Json(TournamentResp {
+        name: tournament.name,
+        game_type: tournament.game_type,
+        format: tournament.format,
+        current_round: tournament.current_round,
+        rounds: tournament.rounds,
+        best_of: tournament.best_of,
+        owner_id: tournament.owner_id,
+        players,
+        player_ids,
+        byes: tournament.byes,
+        started: tournament.games.is_some(),
+        games,
+    }))
 }
 
-#[post("/game/<id>/start")]
-pub fn game_start(
+#[derive(Serialize)]
+pub struct TournamentIndexResp {
+    tournaments: Vec<i32>,
+}
+
+#[get("/tournament/index")]
+pub fn tournament_index(
+    db: DBConn,
+    state: AppReqState,
+) -> Result<Json<TournamentIndexResp>, Error> {
+    let app = AppState::new(db, &*state);
+    let tournaments = app.list_tournaments()?;
+
+    Ok(Json(TournamentIndexResp { tournaments }))
+}
+
+#[derive(Serialize, Debug)]
+pub struct StandingsResp {
+    players: Vec<String>,
+    player_ids: Vec<i32>,
+    standings: Vec<Standing>,
+    crosstable: Vec<Vec<Option<f64>>>,
+}
+
+#[get("/tournament/<id>/standings")]
+pub fn tournament_standings(
     id: i32,
     db: DBConn,
     state: AppReqState,
-    user: User,
-) -> Result<Json<SuccessResp>, Json<ErrorResp>> {
+) -> Result<Json<StandingsResp>, Error> {
     let app = AppState::new(db, &*state);
-    app.start_game(GameId(id), PlayerId::new(user.id))?;
-    Ok(Json(SuccessResp { success: true }))
+    let tournament = app.get_tournament(TournamentId(id))?;
+
+    let results = app.tournament_results(&tournament)?;
+    let standings = compute_standings(&tournament.players, &results, &tournament.byes);
+    let players = app.player_names(
+        &tournament
+            .players
+            .iter()
+            .map(|id| PlayerId::new(*id))
+            .collect::<Vec<PlayerId>>(),
+    )?;
+
+    Ok(Json(StandingsResp {
+        players,
+        player_ids: tournament.players,
+        standings: standings.standings,
+        crosstable: standings.crosstable,
+    }))
+}
+
+#[get("/tournament/<id>/bracket")]
+pub fn tournament_bracket(id: i32, db: DBConn, state: AppReqState) -> Result<Json<Bracket>, Error> {
+    let app = AppState::new(db, &*state);
+    let tournament = app.get_tournament(TournamentId(id))?;
+
+    match tournament.bracket {
+        Some(bracket) => Ok(Json(serde_json::from_str(&bracket)?)),
+        None => Err(Error::NoBracket),
+    }
+}
+
+/// default and maximum number of entries on a page of a paginated list
+const PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+
+/// check the page number (starting at 1) and page size of a paginated list, filling in defaults
+fn check_page(page: Option<u32>, per_page: Option<u32>) -> Result<(u32, u32), Error> {
+    let page = page.unwrap_or(1);
+    let per_page = per_page.unwrap_or(PAGE_SIZE);
+    if page < 1 || per_page < 1 || per_page > MAX_PAGE_SIZE {
+        Err(Error::InvalidPage)
+    } else {
+        Ok((page, per_page))
+    }
 }
 
 #[derive(Serialize)]
-pub struct IndexResp {
-    games: Vec<i32>,
+pub struct LeaderboardResp {
+    /// total number of entries on all pages
+    total: usize,
+    page: u32,
+    per_page: u32,
+    entries: Vec<LeaderboardEntry>,
 }
 
-#[get("/game/index")]
-pub fn game_index(db: DBConn, state: AppReqState) -> Result<Json<IndexResp>, Json<ErrorResp>> {
+/// leaderboard of all users, paginated (pages start at 1)
+#[get("/leaderboard?<page>&<per_page>&<min_games>")]
+pub fn leaderboard(
+    page: Option<u32>,
+    per_page: Option<u32>,
+    min_games: Option<u32>,
+    db: DBConn,
+    state: AppReqState,
+) -> Result<Json<LeaderboardResp>, Error> {
     let app = AppState::new(db, &*state);
-    let games = app.list_games()?;
+    let (page, per_page) = check_page(page, per_page)?;
 
-    Ok(Json(IndexResp { games }))
+    let entries = app.leaderboard(min_games.unwrap_or(0))?;
+    let total = entries.len();
+    let entries = entries
+        .into_iter()
+        .skip((page as usize - 1) * per_page as usize)
+        .take(per_page as usize)
+        .collect();
+
+    Ok(Json(LeaderboardResp {
+        total,
+        page,
+        per_page,
+        entries,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct ProfileResp {
+    id: i32,
+    display_name: String,
+    rating: Option<f64>,
+    glicko: Option<Glicko>,
+    #[serde(flatten)]
+    record: Record,
+}
+
+/// public profile and statistics of a user
+#[get("/user/<id>")]
+pub fn user_profile(id: i32, db: DBConn, state: AppReqState) -> Result<Json<ProfileResp>, Error> {
+    use crate::schema::users;
+
+    let app = AppState::new(db, &*state);
+    let user = users::dsl::users
+        .find(id)
+        .first::<User>(&*app.db)
+        .optional()?
+        .ok_or(Error::InvalidUserId)?;
+
+    let record = app.player_record(PlayerId::new(id))?;
+
+    Ok(Json(ProfileResp {
+        id: user.id,
+        display_name: user.display_name,
+        rating: user.rating,
+        glicko: Glicko::from_columns(
+            user.glicko_rating,
+            user.glicko_deviation,
+            user.glicko_volatility,
+        ),
+        record,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct PlayerResp {
+    id: i32,
+    display_name: String,
+}
+
+#[derive(Serialize)]
+pub struct UserGameResp {
+    id: i32,
+    name: String,
+    game_type: String,
+    rated: bool,
+    /// the user's seat (0 if they moved first)
+    seat: usize,
+    opponents: Vec<PlayerResp>,
+    active: bool,
+    started: bool,
+    /// "win", "loss", or "tie" once the game is finished
+    outcome: Option<&'static str>,
+    moves: i32,
+}
+
+#[derive(Serialize)]
+pub struct UserGamesResp {
+    /// total number of games on all pages
+    total: usize,
+    page: u32,
+    per_page: u32,
+    games: Vec<UserGameResp>,
+}
+
+/// games a user has played or joined, newest first, paginated (pages start at 1)
+#[get("/user/<id>/games?<page>&<per_page>")]
+pub fn user_games(
+    id: i32,
+    page: Option<u32>,
+    per_page: Option<u32>,
+    db: DBConn,
+    state: AppReqState,
+) -> Result<Json<UserGamesResp>, Error> {
+    use crate::schema::users;
+
+    let app = AppState::new(db, &*state);
+    let (page, per_page) = check_page(page, per_page)?;
+    users::dsl::users
+        .find(id)
+        .select(users::dsl::id)
+        .first::<i32>(&*app.db)
+        .optional()?
+        .ok_or(Error::InvalidUserId)?;
+
+    let player_id = PlayerId::new(id);
+    let total = app.count_player_games(player_id)? as usize;
+    let mut games = vec![];
+    for game in app.player_games(
+        player_id,
+        (page as i64 - 1) * per_page as i64,
+        per_page as i64,
+    )? {
+        let seat = game.get_player_index(player_id)? as usize;
+        let opponents = game
+            .players
+            .iter()
+            .filter(|player| **player != player_id)
+            .cloned()
+            .collect::<Vec<PlayerId>>();
+        let opponents = opponents
+            .iter()
+            .zip(app.player_names(&opponents)?)
+            .map(|(player, display_name)| PlayerResp {
+                id: player.id(),
+                display_name,
+            })
+            .collect();
+        let outcome = game.result().and_then(|result| match result.winner {
+            _ if result.tie => Some("tie"),
+            Some(winner) if winner == seat => Some("win"),
+            Some(_) => Some("loss"),
+            None => None,
+        });
+
+        games.push(UserGameResp {
+            id: game.id.id(),
+            name: game.name.clone(),
+            game_type: game.game_type.to_string(),
+            rated: game.rated,
+            seat,
+            opponents,
+            active: game.active(),
+            started: game.started(),
+            outcome,
+            moves: game.moves,
+        });
+    }
+
+    Ok(Json(UserGamesResp {
+        total,
+        page,
+        per_page,
+        games,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game_manage::{
+        webhook_signature, GameServer, BOT_AUTH_ATTEMPTS, BOT_AUTH_TIMEOUT, MAX_STREAMS_PER_CLIENT,
+        MAX_WAITS_PER_PLAYER, WEBHOOK_SIGNATURE_HEADER,
+    };
+    use crate::shared::{DBConn, DBPool};
+    use crate::{app, game_registry, run_migrations};
+    use diesel::prelude::*;
+    use rocket::config::{Config, Environment, LoggingLevel, Value};
+    use rocket::http::{ContentType, Header};
+    use rocket::local::Client;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{mpsc, Arc, Barrier};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tungstenite::{Message, WebSocket};
+    use uuid::Uuid;
+
+    /// threads making moves for each player
+    const THREADS_PER_PLAYER: usize = 8;
+    /// moves to make before stopping
+    const MOVES: i32 = 40;
+    /// games played at once in the benchmark
+    const BENCH_GAMES: usize = 500;
+    /// threads making moves in the benchmark
+    const BENCH_THREADS: usize = 32;
+    /// moves made in each game in the benchmark. They fill the first rows of the board, so no one wins
+    const BENCH_MOVES: usize = 20;
+
+    /// an app using the database in DATABASE_URL
+    fn client() -> Client {
+        client_for(GameServer::new(game_registry()))
+    }
+
+    /// an app using the database in DATABASE_URL, hosting games with the given server
+    fn client_for(server: GameServer) -> Client {
+        run_migrations::run_migrations();
+        let mut db = HashMap::new();
+        db.insert("url", Value::from(std::env::var("DATABASE_URL").unwrap()));
+        db.insert("pool_size", Value::from(BENCH_THREADS as i64 + 8));
+        let mut databases = HashMap::new();
+        databases.insert("db", Value::from(db));
+        let config = Config::build(Environment::Development)
+            .extra("databases", databases)
+            .extra("max_streams", 16)
+            .extra("webhook_allowed_hosts", vec!["127.0.0.1"])
+            .log_level(LoggingLevel::Critical)
+            .finalize()
+            .unwrap();
+
+        Client::new(app(rocket::custom(config), server)).unwrap()
+    }
+
+    fn post(client: &Client, path: &str, key: &str, body: &str) -> serde_json::Value {
+        let mut res = client
+            .post(path)
+            .header(ContentType::Form)
+            .header(Header::new("x-api-key", key.to_string()))
+            .body(body)
+            .dispatch();
+        serde_json::from_str(&res.body_string().unwrap()).unwrap()
+    }
+
+    fn get(client: &Client, path: &str) -> serde_json::Value {
+        let mut res = client.get(path).dispatch();
+        serde_json::from_str(&res.body_string().unwrap()).unwrap()
+    }
+
+    /// create a user and return their api key
+    fn new_user(client: &Client) -> String {
+        let name = Uuid::new_v4().simple().to_string()[..16].to_string();
+        let login = format!("username={}&password=pw", name);
+        post(
+            client,
+            "/api/user/new",
+            "",
+            &format!("{}&display_name={}", login, name),
+        );
+        post(client, "/api/session/new", "", &login);
+        let key = post(client, "/api/user/generate_api", "", "")["key"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        // later requests are authorized by api key
+        post(client, "/api/session/delete", "", "");
+
+        key
+    }
+
+    /// create a started game between two players and return its id
+    fn new_started_game(client: &Client, keys: &[String]) -> String {
+        let id = post(
+            client,
+            "/api/game/new",
+            &keys[0],
+            "name=race&game_type=gomoku",
+        )["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        for key in keys {
+            post(client, &format!("/api/game/{}/join", id), key, "");
+        }
+        post(client, &format!("/api/game/{}/start", id), &keys[0], "");
+
+        id
+    }
+
+    /// many requests make moves in the same game at once. every move that succeeds should be kept, and moves made
+    /// from a stale copy of the game should be rejected instead of overwriting other moves
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn concurrent_moves() {
+        let client = Arc::new(client());
+        let keys = vec![new_user(&client), new_user(&client)];
+        let id = new_started_game(&client, &keys);
+
+        let threads = keys
+            .iter()
+            .flat_map(|key| (0..THREADS_PER_PLAYER).map(move |_| key.clone()))
+            .map(|key| {
+                let client = client.clone();
+                let id = id.clone();
+                thread::spawn(move || {
+                    let mut made = 0;
+                    loop {
+                        let game = get(&client, &format!("/api/game/{}", id));
+                        if game["status"] != "running"
+                            || game["moves"].as_i64().unwrap() >= MOVES as i64
+                        {
+                            return made;
+                        }
+                        // every thread plays the first empty square, so threads for the same player race each other
+                        let board = game["state"]["board"].as_array().unwrap();
+                        let (x, y) = (0..board.len())
+                            .flat_map(|x| (0..board.len()).map(move |y| (x, y)))
+                            .find(|&(x, y)| board[x][y] == -1)
+                            .unwrap();
+                        let res = post(
+                            &client,
+                            &format!("/api/game/{}/move", id),
+                            &key,
+                            &format!("x={}&y={}", x, y),
+                        );
+                        if res["success"] == true {
+                            made += 1;
+                        } else {
+                            let error = res["error"].as_str().unwrap();
+                            assert!(!error.starts_with("database error"), "{}", error);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut made = 0;
+        for thread in threads {
+            made += thread.join().unwrap();
+        }
+
+        let game = get(&client, &format!("/api/game/{}", id));
+        let stones = game["state"]["board"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|col| col.as_array().unwrap())
+            .filter(|square| **square != -1)
+            .count();
+        let moves = get(&client, &format!("/api/game/{}/moves", id));
+        assert_eq!(game["moves"], made);
+        assert_eq!(stones, made as usize);
+        assert_eq!(moves["moves"].as_array().unwrap().len(), made as usize);
+    }
+
+    /// waiting requests return as soon as a move makes it the player's turn, and a player can only have a few waiting at once
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn wait_woken_by_move() {
+        let client = Arc::new(client());
+        let keys = vec![new_user(&client), new_user(&client)];
+        let id = new_started_game(&client, &keys);
+        let path = format!("/api/game/{}/wait?timeout=30", id);
+        let wait = |client: &Client, path: &str, key: &str| -> serde_json::Value {
+            let mut res = client
+                .get(path)
+                .header(Header::new("x-api-key", key.to_string()))
+                .dispatch();
+            serde_json::from_str(&res.body_string().unwrap()).unwrap()
+        };
+
+        // the second player waits for the first player to move
+        let start = Instant::now();
+        let threads = (0..MAX_WAITS_PER_PLAYER)
+            .map(|_| {
+                let (client, path, key) = (client.clone(), path.clone(), keys[1].clone());
+                thread::spawn(move || wait(&client, &path, &key))
+            })
+            .collect::<Vec<_>>();
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(wait(&client, &path, &keys[1])["code"], "too_many_waits");
+
+        post(
+            &client,
+            &format!("/api/game/{}/move", id),
+            &keys[0],
+            "x=0&y=0",
+        );
+        for thread in threads {
+            let res = thread.join().unwrap();
+            assert_eq!(
+                (&res["needed"], &res["moves"]),
+                (&true.into(), &1.into()),
+                "{}",
+                res
+            );
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    /// read an event stream until it has the given text
+    fn read_stream_until(body: &mut dyn Read, until: &str) -> String {
+        // a read returning 0 only ends a message, so the stream is read until it has the text
+        let (mut text, mut last_len) = (String::new(), 1);
+        while !text.contains(until) {
+            let mut buf = vec![0; 65536];
+            let len = body.read(&mut buf).unwrap();
+            // the stream ended if two reads in a row return 0
+            assert!(len > 0 || last_len > 0, "stream ended early: {}", text);
+            last_len = len;
+            text.push_str(&String::from_utf8(buf[..len].to_vec()).unwrap());
+        }
+
+        text
+    }
+
+    /// a game's event stream has its state, then every change to it in order, and ends when the game ends.
+    /// a client can only have a few streams open at once
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn event_stream() {
+        let client = client();
+        let keys = vec![new_user(&client), new_user(&client)];
+        let id = post(
+            &client,
+            "/api/game/new",
+            &keys[0],
+            "name=events&game_type=gomoku",
+        )["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let path = format!("/api/game/{}/events", id);
+
+        let mut streams = (0..MAX_STREAMS_PER_CLIENT)
+            .map(|_| client.get(path.clone()).dispatch())
+            .collect::<Vec<_>>();
+        assert_eq!(get(&client, &path)["code"], "too_many_streams");
+        // closing a stream lets another be opened
+        streams.pop();
+        streams.push(client.get(path.clone()).dispatch());
+
+        for key in &keys {
+            post(&client, &format!("/api/game/{}/join", id), key, "");
+        }
+        post(&client, &format!("/api/game/{}/start", id), &keys[0], "");
+        // the first player gets five in a row
+        for x in 0..5 {
+            for (y, key) in keys.iter().enumerate() {
+                if x < 4 || y == 0 {
+                    let body = format!("x={}&y={}", x, y);
+                    post(&client, &format!("/api/game/{}/move", id), key, &body);
+                }
+            }
+        }
+
+        let body = streams[0].body().unwrap().into_inner();
+        let text = read_stream_until(body, "event: finish");
+        assert_eq!(body.read(&mut [0; 16]).unwrap(), 0);
+
+        let events = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("event: "))
+            .collect::<Vec<&str>>();
+        let mut expected = vec!["state", "join", "join", "start"];
+        expected.extend(vec!["move"; 9]);
+        expected.push("finish");
+        assert_eq!(events, expected);
+        let moves = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| {
+                serde_json::from_str::<serde_json::Value>(data).unwrap()["game"]["moves"].as_i64()
+            })
+            .collect::<Vec<Option<i64>>>();
+        let mut expected = vec![0, 0, 0, 0];
+        expected.extend(1..=9);
+        expected.push(9);
+        assert_eq!(moves, expected.into_iter().map(Some).collect::<Vec<_>>());
+    }
+
+    /// a save rejected by the db's version check leaves the cache alone and isn't sent to clients
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn stale_save_not_published() {
+        let client = client();
+        let keys = vec![new_user(&client), new_user(&client)];
+        let id = post(
+            &client,
+            "/api/game/new",
+            &keys[0],
+            "name=stale&game_type=gomoku",
+        )["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let mut stream = client.get(format!("/api/game/{}/events", id)).dispatch();
+
+        // another server saved the game since it was loaded
+        let db = DBConn::get_one(client.rocket()).unwrap();
+        diesel::sql_query(format!(
+            "update db_games set version = version + 1 where id = {}",
+            id
+        ))
+        .execute(&*db)
+        .unwrap();
+        let res = post(&client, &format!("/api/game/{}/join", id), &keys[1], "");
+        assert_eq!(res["code"], "game_changed");
+        assert_eq!(
+            get(&client, &format!("/api/game/{}", id))["players"],
+            json!([])
+        );
+
+        // the next join loads the game again, and is the first change clients see
+        let res = post(&client, &format!("/api/game/{}/join", id), &keys[0], "");
+        assert_eq!(res["success"], true, "{}", res);
+        let text = read_stream_until(stream.body().unwrap().into_inner(), "event: join");
+        let joined = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .last()
+            .map(|data| serde_json::from_str::<serde_json::Value>(data).unwrap())
+            .unwrap();
+        let owner = client
+            .get("/api/user")
+            .header(Header::new("x-api-key", keys[0].clone()))
+            .dispatch()
+            .body_string()
+            .map(|body| serde_json::from_str::<serde_json::Value>(&body).unwrap())
+            .unwrap();
+        assert_eq!(joined["game"]["player_ids"], json!([owner["id"]]));
+    }
+
+    /// a move that fails to save isn't kept in the cache or the move log
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn failed_move_not_kept() {
+        let client = client();
+        let keys = vec![new_user(&client), new_user(&client)];
+        let id = new_started_game(&client, &keys);
+        let move_path = format!("/api/game/{}/move", id);
+        for x in 0..4 {
+            post(&client, &move_path, &keys[0], &format!("x={}&y=0", x));
+            post(&client, &move_path, &keys[1], &format!("x={}&y=1", x));
+        }
+
+        // another server saved the game since it was loaded, so the winning move's write is rejected
+        let db = DBConn::get_one(client.rocket()).unwrap();
+        diesel::sql_query(format!(
+            "update db_games set version = version + 100 where id = {}",
+            id
+        ))
+        .execute(&*db)
+        .unwrap();
+        let res = post(&client, &move_path, &keys[0], "x=4&y=0");
+        assert_eq!(res["code"], "game_changed");
+
+        let game = get(&client, &format!("/api/game/{}", id));
+        assert_eq!(
+            (&game["moves"], &game["status"]),
+            (&8.into(), &"running".into())
+        );
+        let moves = get(&client, &format!("/api/game/{}/moves", id));
+        assert_eq!(moves["moves"].as_array().unwrap().len(), 8);
+    }
+
+    /// rated games between the same players (in opposite seats) that finish at once both save, with their ratings
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn rated_games_finish_at_once() {
+        use crate::schema::rating_history;
+
+        let client = Arc::new(client());
+        let keys = vec![new_user(&client), new_user(&client)];
+        let mut game_ids = vec![];
+        for _ in 0..5 {
+            // the same players in opposite seats, each one move from the first player winning
+            let games = [keys.clone(), vec![keys[1].clone(), keys[0].clone()]]
+                .iter()
+                .map(|seats| {
+                    let id = post(
+                        &client,
+                        "/api/game/new",
+                        &seats[0],
+                        "name=rated&game_type=gomoku&rated=true",
+                    )["id"]
+                        .as_str()
+                        .unwrap()
+                        .to_string();
+                    for key in seats {
+                        post(&client, &format!("/api/game/{}/join", id), key, "");
+                    }
+                    post(&client, &format!("/api/game/{}/start", id), &seats[0], "");
+                    for x in 0..4 {
+                        for (y, key) in seats.iter().enumerate() {
+                            let body = format!("x={}&y={}", x, y);
+                            post(&client, &format!("/api/game/{}/move", id), key, &body);
+                        }
+                    }
+                    (id, seats[0].clone())
+                })
+                .collect::<Vec<(String, String)>>();
+
+            let barrier = Arc::new(Barrier::new(games.len()));
+            let threads = games
+                .iter()
+                .cloned()
+                .map(|(id, key)| {
+                    let (client, barrier) = (client.clone(), barrier.clone());
+                    thread::spawn(move || {
+                        barrier.wait();
+                        post(&client, &format!("/api/game/{}/move", id), &key, "x=4&y=0")
+                    })
+                })
+                .collect::<Vec<_>>();
+            for thread in threads {
+                let res = thread.join().unwrap();
+                assert_eq!(res["success"], true, "{}", res);
+            }
+            game_ids.extend(games.iter().map(|(id, _)| id.parse::<i32>().unwrap()));
+        }
+
+        let db = DBConn::get_one(client.rocket()).unwrap();
+        let history = rating_history::dsl::rating_history
+            .filter(rating_history::dsl::game_id.eq_any(&game_ids))
+            .count()
+            .get_result::<i64>(&*db)
+            .unwrap();
+        assert_eq!(history, 2 * game_ids.len() as i64);
+    }
+
+    /// when the last games of a swiss round or the feeder matches of a bracket finish at once, the tournament still advances
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn tournament_games_finish_at_once() {
+        let client = Arc::new(client());
+        for format in &["swiss", "single_elimination"] {
+            let keys = (0..4).map(|_| new_user(&client)).collect::<Vec<String>>();
+            let keys_by_id = keys
+                .iter()
+                .map(|key| {
+                    let user = client
+                        .get("/api/user")
+                        .header(Header::new("x-api-key", key.clone()))
+                        .dispatch()
+                        .body_string()
+                        .map(|body| serde_json::from_str::<serde_json::Value>(&body).unwrap())
+                        .unwrap();
+                    (user["id"].as_i64().unwrap(), key.clone())
+                })
+                .collect::<HashMap<i64, String>>();
+            let id = post(
+                &client,
+                "/api/tournament/new",
+                &keys[0],
+                &format!("name=at_once&game_type=gomoku&format={}&rounds=2", format),
+            )["id"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            for key in &keys {
+                post(&client, &format!("/api/tournament/{}/join", id), key, "");
+            }
+            post(
+                &client,
+                &format!("/api/tournament/{}/start", id),
+                &keys[0],
+                "",
+            );
+
+            // both games of the first round are played until the first player is one move from winning
+            let games = get(&client, &format!("/api/tournament/{}", id))["games"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|game| {
+                    let seats = game["player_ids"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|id| keys_by_id[&id.as_i64().unwrap()].clone())
+                        .collect::<Vec<String>>();
+                    let move_path = format!("/api/game/{}/move", game["id"]);
+                    for x in 0..4 {
+                        for (y, key) in seats.iter().enumerate() {
+                            post(&client, &move_path, key, &format!("x={}&y={}", x, y));
+                        }
+                    }
+                    (move_path, seats[0].clone())
+                })
+                .collect::<Vec<(String, String)>>();
+            assert_eq!(games.len(), 2);
+
+            let barrier = Arc::new(Barrier::new(games.len()));
+            let threads = games
+                .into_iter()
+                .map(|(move_path, key)| {
+                    let (client, barrier) = (client.clone(), barrier.clone());
+                    thread::spawn(move || {
+                        barrier.wait();
+                        post(&client, &move_path, &key, "x=4&y=0")
+                    })
+                })
+                .collect::<Vec<_>>();
+            for thread in threads {
+                let res = thread.join().unwrap();
+                assert_eq!(res["success"], true, "{}", res);
+            }
+
+            let tournament = get(&client, &format!("/api/tournament/{}", id));
+            let games = tournament["games"].as_array().unwrap();
+            let expected = if *format == "swiss" { 4 } else { 3 };
+            assert_eq!(
+                games.len(),
+                expected,
+                "{} didn't advance: {}",
+                format,
+                tournament
+            );
+        }
+    }
+
+    /// moves sent with a ply are only made at that ply, and retrying a move with the same idempotency key
+    /// (even many times at once) makes it once and returns the game after it
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn ply_and_idempotency() {
+        let client = Arc::new(client());
+        let keys = vec![new_user(&client), new_user(&client)];
+        let id = new_started_game(&client, &keys);
+        let move_path = |query: &str| format!("/api/game/{}/move?{}", id, query);
+
+        let res = post(&client, &move_path("ply=2"), &keys[0], "x=0&y=0");
+        assert_eq!(res["code"], "stale_ply");
+        let res = post(
+            &client,
+            &move_path("ply=1&idempotency_key=a"),
+            &keys[0],
+            "x=0&y=0",
+        );
+        assert_eq!((&res["success"], &res["ply"]), (&true.into(), &1.into()));
+        let retried = post(
+            &client,
+            &move_path("ply=1&idempotency_key=a"),
+            &keys[0],
+            "x=0&y=0",
+        );
+        assert_eq!(retried, res);
+        let res = post(
+            &client,
+            &move_path("idempotency_key=a"),
+            &keys[0],
+            "x=0&y=1",
+        );
+        assert_eq!(res["code"], "idempotency_key_reused");
+
+        let threads = (0..THREADS_PER_PLAYER)
+            .map(|_| {
+                let client = client.clone();
+                let path = move_path("ply=2&idempotency_key=b");
+                let key = keys[1].clone();
+                thread::spawn(move || post(&client, &path, &key, "x=1&y=0"))
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            let res = thread.join().unwrap();
+            assert_eq!(
+                (&res["success"], &res["ply"]),
+                (&true.into(), &2.into()),
+                "{}",
+                res
+            );
+        }
+
+        let moves = get(&client, &format!("/api/game/{}/moves", id));
+        assert_eq!(moves["moves"].as_array().unwrap().len(), 2);
+    }
+
+    /// read the signature and body of a webhook sent to a stand-in server
+    fn read_webhook(stream: &TcpStream) -> (String, String) {
+        let mut reader = BufReader::new(stream);
+        let mut signature = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut header = line.trim_end().splitn(2, ": ");
+            match (header.next().unwrap(), header.next()) {
+                ("", None) => break,
+                (name, Some(value)) if name.eq_ignore_ascii_case(WEBHOOK_SIGNATURE_HEADER) => {
+                    signature = value.to_string()
+                }
+                (name, Some(value)) if name.eq_ignore_ascii_case("content-length") => {
+                    length = value.parse().unwrap()
+                }
+                _ => {}
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        (signature, String::from_utf8(body).unwrap())
+    }
+
+    /// webhooks can only be posted to public hosts over http or https, unless the host is allowed
+    #[test]
+    fn webhook_urls() {
+        let server = GameServer::new(game_registry());
+        for url in &[
+            "ftp://8.8.8.8/turn",
+            "http:/turn",
+            "http://127.0.0.1:8000/turn",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/turn",
+            "https://192.168.0.1/turn",
+            "http://100.64.0.1/turn",
+            "http://0.0.0.0/turn",
+            "http://[::1]/turn",
+            "http://[fd00::1]/turn",
+            "http://[::ffff:127.0.0.1]/turn",
+        ] {
+            assert!(server.check_webhook_url(url).is_err(), "{}", url);
+        }
+        for url in &[
+            "http://8.8.8.8/turn",
+            "https://[2001:4860:4860::8888]:8443/",
+        ] {
+            assert!(server.check_webhook_url(url).is_ok(), "{}", url);
+        }
+
+        server.set_webhook_allowed_hosts(vec!["127.0.0.1".to_string()]);
+        assert!(server
+            .check_webhook_url("http://127.0.0.1:8000/turn")
+            .is_ok());
+        assert!(server.check_webhook_url("http://10.1.2.3/turn").is_err());
+    }
+
+    /// players with webhooks are sent signed turns when games start waiting on them, and failed webhooks are retried
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn webhook_turns() {
+        // a stand-in for a bot's server, which fails the first webhook it gets
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/turn", listener.local_addr().unwrap());
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                sender.send(read_webhook(&stream)).unwrap();
+                let status = if i == 0 {
+                    "500 Internal Server Error"
+                } else {
+                    "200 OK"
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+
+        let client = client();
+        let keys = [new_user(&client), new_user(&client)];
+        let secret = post(
+            &client,
+            "/api/user/webhook",
+            &keys[1],
+            &format!("url={}", url),
+        )["secret"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let id = new_started_game(&client, &keys);
+        post(
+            &client,
+            &format!("/api/game/{}/move", id),
+            &keys[0],
+            "x=7&y=7",
+        );
+
+        let (failed_signature, failed_body) =
+            received.recv_timeout(Duration::from_secs(10)).unwrap();
+        let (signature, body) = received.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!((&failed_signature, &failed_body), (&signature, &body));
+        assert_eq!(
+            signature,
+            format!("sha256={}", webhook_signature(&secret, &body))
+        );
+
+        let turn = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(turn["type"], "turn");
+        assert_eq!(turn["game_id"].to_string(), id);
+        assert_eq!((&turn["moves"], &turn["version"]), (&1.into(), &4.into()));
+        // the board is as seen by the second player, so the first player's stone is an opponent's
+        assert_eq!(turn["state"]["board"][7][7], 1);
+        assert!(received.try_recv().is_err());
+    }
+
+    /// bots authenticate (and are disconnected after failing too many times or taking too long), are sent turns when
+    /// games wait on them, and make moves over their websocket. only a limited number can be connected at once
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn bot_socket() {
+        let server = GameServer::new(game_registry());
+        let client = client_for(server.clone());
+        let pool = DBPool::from_rocket(client.rocket()).unwrap();
+        let address = server.listen_bots(pool, "127.0.0.1:0", 2).unwrap();
+        let connect = || {
+            let stream = TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(BOT_AUTH_TIMEOUT + Duration::from_secs(5)))
+                .unwrap();
+            tungstenite::client("ws://localhost/", stream)
+                .ok()
+                .map(|(socket, _)| socket)
+        };
+        let send = |socket: &mut WebSocket<TcpStream>, message: &str| {
+            socket
+                .write_message(Message::Text(message.to_string()))
+                .unwrap()
+        };
+        let read = |socket: &mut WebSocket<TcpStream>| match socket.read_message().unwrap() {
+            Message::Text(text) => serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            message => panic!("unexpected message {:?}", message),
+        };
+        let closed = |socket: &mut WebSocket<TcpStream>| {
+            matches!(socket.read_message(), Ok(Message::Close(_)) | Err(_))
+        };
+        let keys = vec![new_user(&client), new_user(&client)];
+
+        let mut socket = connect().unwrap();
+        for _ in 0..BOT_AUTH_ATTEMPTS {
+            send(&mut socket, r#"{ "api_key": "wrong" }"#);
+            assert_eq!(read(&mut socket)["code"], "invalid_api_key");
+        }
+        assert!(closed(&mut socket));
+        thread::sleep(Duration::from_millis(200));
+
+        let mut bot = connect().unwrap();
+        send(&mut bot, &format!(r#"{{ "api_key": "{}" }}"#, keys[1]));
+        assert_eq!(read(&mut bot)["type"], "authenticated");
+        // the limit is 2 connections, so a third is closed right away
+        let mut idle = connect().unwrap();
+        assert!(connect().is_none());
+
+        let id = new_started_game(&client, &keys);
+        post(
+            &client,
+            &format!("/api/game/{}/move", id),
+            &keys[0],
+            "x=7&y=7",
+        );
+        let turn = read(&mut bot);
+        assert_eq!((&turn["type"], &turn["moves"]), (&"turn".into(), &1.into()));
+        assert_eq!(turn["game_id"].to_string(), id);
+
+        // messages sent at once are all replied to
+        send(
+            &mut bot,
+            &format!(r#"{{ "game_id": {}, "move": "x=0&y=0" }}"#, id),
+        );
+        send(
+            &mut bot,
+            &format!(r#"{{ "game_id": {}, "move": "x=1&y=0" }}"#, id),
+        );
+        assert_eq!(read(&mut bot)["success"], true);
+        assert_eq!(read(&mut bot)["code"], "wrong_turn");
+        assert_eq!(get(&client, &format!("/api/game/{}", id))["moves"], 2);
+
+        // a bot that doesn't authenticate is disconnected
+        let start = Instant::now();
+        assert!(closed(&mut idle));
+        assert!(start.elapsed() <= BOT_AUTH_TIMEOUT + Duration::from_secs(1));
+
+        // a bot that disconnects is forgotten
+        bot.close(None).unwrap();
+        assert!(closed(&mut bot));
+        thread::sleep(Duration::from_millis(200));
+        assert!(server.bots.lock().unwrap().players.is_empty());
+    }
+
+    /// measure how many moves per second are made when many games are played at once.
+    /// run with `cargo test --release bench_simultaneous_games -- --ignored --nocapture`
+    #[test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    fn bench_simultaneous_games() {
+        let client = Arc::new(client());
+        let keys = vec![new_user(&client), new_user(&client)];
+        let games = (0..BENCH_GAMES)
+            .map(|_| new_started_game(&client, &keys))
+            .collect::<Vec<String>>();
+
+        let start = Instant::now();
+        let threads = (0..BENCH_THREADS)
+            .map(|thread| {
+                let client = client.clone();
+                let keys = keys.clone();
+                // each thread plays its share of the games, making a move in each in turn
+                let games = games
+                    .iter()
+                    .skip(thread)
+                    .step_by(BENCH_THREADS)
+                    .cloned()
+                    .collect::<Vec<String>>();
+                thread::spawn(move || {
+                    for ply in 0..BENCH_MOVES {
+                        let (x, y) = (ply / 15, ply % 15);
+                        for id in &games {
+                            let res = post(
+                                &client,
+                                &format!("/api/game/{}/move", id),
+                                &keys[ply % 2],
+                                &format!("x={}&y={}", x, y),
+                            );
+                            assert_eq!(res["success"], true, "{}", res);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let moves = BENCH_GAMES * BENCH_MOVES;
+        println!(
+            "{} moves in {} simultaneous games in {:.2}s: {:.0} moves/s",
+            moves,
+            BENCH_GAMES,
+            elapsed,
+            moves as f64 / elapsed
+        );
+    }
 }
@@ -0,0 +1,308 @@
+use crate::game::GameRegistry;
+use crate::game_manage::{games_from_db, GameStatus};
+use crate::models::DbGame;
+use crate::shared::{Error, ErrorResp};
+use crate::standings::GameResult;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// default number of finished rated games (in the order they finished) in each rating period
+pub const GAMES_PER_PERIOD: usize = 100;
+
+/// rating, deviation, and volatility given to players before their first rated game
+pub const INITIAL_RATING: f64 = 1500.0;
+pub const INITIAL_DEVIATION: f64 = 350.0;
+pub const INITIAL_VOLATILITY: f64 = 0.06;
+
+/// constrains the change in volatility over time
+const TAU: f64 = 0.5;
+/// convergence tolerance when solving for the new volatility
+const EPSILON: f64 = 0.000001;
+/// conversion factor between the glicko and glicko-2 scales
+const SCALE: f64 = 173.7178;
+
+/// A player's glicko-2 rating
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Glicko {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Glicko {
+    fn default() -> Glicko {
+        Glicko {
+            rating: INITIAL_RATING,
+            deviation: INITIAL_DEVIATION,
+            volatility: INITIAL_VOLATILITY,
+        }
+    }
+}
+
+impl Glicko {
+    /// the glicko rating stored on a user, if they have one
+    pub fn from_columns(
+        rating: Option<f64>,
+        deviation: Option<f64>,
+        volatility: Option<f64>,
+    ) -> Option<Glicko> {
+        Some(Glicko {
+            rating: rating?,
+            deviation: deviation?,
+            volatility: volatility?,
+        })
+    }
+
+    fn mu(&self) -> f64 {
+        (self.rating - INITIAL_RATING) / SCALE
+    }
+
+    fn phi(&self) -> f64 {
+        self.deviation / SCALE
+    }
+
+    /// the player's rating after a rating period in which they played the given games.
+    /// each game is the opponent's rating at the start of the period and the player's score (1 for a win, 0.5 for a tie, 0 for a loss)
+    pub fn rate(&self, games: &[(Glicko, f64)]) -> Glicko {
+        let mu = self.mu();
+        let phi = self.phi();
+        let sigma = self.volatility;
+
+        // players who didn't play only become less certain
+        if games.is_empty() {
+            return Glicko {
+                rating: self.rating,
+                deviation: ((phi * phi + sigma * sigma).sqrt() * SCALE).min(INITIAL_DEVIATION),
+                volatility: sigma,
+            };
+        }
+
+        // estimated variance and improvement from the period's games
+        let mut v_inv = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, score) in games {
+            let g = g(opponent.phi());
+            let e = expected(mu, opponent.mu(), g);
+            v_inv += g * g * e * (1.0 - e);
+            improvement += g * (score - e);
+        }
+        let v = 1.0 / v_inv;
+        let delta = v * improvement;
+
+        // new volatility, found with the illinois algorithm
+        let a = (sigma * sigma).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2))
+                - (x - a) / (TAU * TAU)
+        };
+        let mut lower = a;
+        let mut upper = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let mut f_lower = f(lower);
+        let mut f_upper = f(upper);
+        while (upper - lower).abs() > EPSILON {
+            let next = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+            let f_next = f(next);
+            if f_next * f_upper <= 0.0 {
+                lower = upper;
+                f_lower = f_upper;
+            } else {
+                f_lower /= 2.0;
+            }
+            upper = next;
+            f_upper = f_next;
+        }
+        let new_sigma = (lower / 2.0).exp();
+
+        let phi_star = (phi * phi + new_sigma * new_sigma).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + v_inv).sqrt();
+        let new_mu = mu + new_phi * new_phi * improvement;
+
+        Glicko {
+            rating: new_mu * SCALE + INITIAL_RATING,
+            deviation: (new_phi * SCALE).min(INITIAL_DEVIATION),
+            volatility: new_sigma,
+        }
+    }
+}
+
+/// weighting of a game against an opponent by the opponent's deviation
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (std::f64::consts::PI * std::f64::consts::PI)).sqrt()
+}
+
+/// expected score of a player against an opponent
+fn expected(mu: f64, opponent_mu: f64, g: f64) -> f64 {
+    1.0 / (1.0 + (-g * (mu - opponent_mu)).exp())
+}
+
+/// the pairwise scores (player, opponent, player's score) in a finished game.
+/// pairs where neither player won (another player won) aren't counted
+fn pairwise_scores(result: &GameResult) -> Vec<(i32, i32, f64)> {
+    let mut scores = Vec::new();
+    for i in 0..result.players.len() {
+        for j in 0..result.players.len() {
+            if i == j {
+                continue;
+            }
+            let score = if result.tie {
+                0.5
+            } else if result.winner == Some(i) {
+                1.0
+            } else if result.winner == Some(j) {
+                0.0
+            } else {
+                continue;
+            };
+            scores.push((result.players[i], result.players[j], score));
+        }
+    }
+
+    scores
+}
+
+/// compute glicko ratings from the results of finished rated games, in the order given.
+/// games are split into rating periods of games_per_period games
+pub fn compute_ratings(results: &[GameResult], games_per_period: usize) -> BTreeMap<i32, Glicko> {
+    let mut ratings = BTreeMap::<i32, Glicko>::new();
+
+    for period in results.chunks(games_per_period) {
+        let mut games = BTreeMap::<i32, Vec<(Glicko, f64)>>::new();
+        for result in period {
+            for (player, opponent, score) in pairwise_scores(result) {
+                let opponent = ratings.get(&opponent).copied().unwrap_or_default();
+                games.entry(player).or_default().push((opponent, score));
+            }
+        }
+
+        let mut next = BTreeMap::new();
+        for player in ratings.keys().chain(games.keys()) {
+            let rating = ratings.get(player).copied().unwrap_or_default();
+            let played = games.get(player).map(|g| &g[..]).unwrap_or(&[]);
+            next.insert(*player, rating.rate(played));
+        }
+        ratings = next;
+    }
+
+    ratings
+}
+
+/// what recomputing the ratings did
+pub struct Recomputed {
+    /// number of games rated
+    pub games: usize,
+    /// number of players rated
+    pub players: usize,
+    /// games left out of the ratings (as game id, problem)
+    pub skipped: Vec<(i32, String)>,
+}
+
+/// rebuild every user's glicko rating from the history of finished rated games, in the order they finished.
+/// games are split into rating periods of games_per_period games. games that can't be loaded are left out
+pub fn recompute_ratings(
+    conn: &PgConnection,
+    kinds: &GameRegistry,
+    games_per_period: usize,
+) -> Result<Recomputed, Error> {
+    use crate::schema::db_games::dsl::*;
+    use crate::schema::users;
+
+    conn.transaction::<_, Error, _>(|| {
+        let entries = db_games
+            .filter(rated.eq(true))
+            .filter(status.eq(GameStatus::Finished.as_str()))
+            .order((finished_at.asc(), id.asc()))
+            .load::<DbGame>(conn)?;
+        let ids = entries.iter().map(|entry| entry.id).collect::<Vec<i32>>();
+
+        let mut results = vec![];
+        let mut skipped = vec![];
+        for (&game_id, game) in ids.iter().zip(games_from_db(conn, entries, kinds)?) {
+            match game.map(|game| game.result()) {
+                Ok(Some(result)) => results.push(result),
+                Ok(None) => skipped.push((game_id, "finished game has no result".to_string())),
+                Err(err) => skipped.push((
+                    game_id,
+                    format!("game can't be loaded: {}", ErrorResp::from(err).error),
+                )),
+            }
+        }
+        let ratings = compute_ratings(&results, games_per_period);
+
+        diesel::update(users::table)
+            .set((
+                users::dsl::glicko_rating.eq(None::<f64>),
+                users::dsl::glicko_deviation.eq(None::<f64>),
+                users::dsl::glicko_volatility.eq(None::<f64>),
+            ))
+            .execute(conn)?;
+        for (player, rating) in &ratings {
+            diesel::update(users::dsl::users.find(player))
+                .set((
+                    users::dsl::glicko_rating.eq(rating.rating),
+                    users::dsl::glicko_deviation.eq(rating.deviation),
+                    users::dsl::glicko_volatility.eq(rating.volatility),
+                ))
+                .execute(conn)?;
+        }
+
+        Ok(Recomputed {
+            games: results.len(),
+            players: ratings.len(),
+            skipped,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_glickman_worked_example() {
+        // the example from Glickman's "Example of the Glicko-2 system"
+        let player = Glicko {
+            rating: 1500.0,
+            deviation: 200.0,
+            volatility: 0.06,
+        };
+        let opponent = |rating, deviation| Glicko {
+            rating,
+            deviation,
+            volatility: INITIAL_VOLATILITY,
+        };
+        let rated = player.rate(&[
+            (opponent(1400.0, 30.0), 1.0),
+            (opponent(1550.0, 100.0), 0.0),
+            (opponent(1700.0, 300.0), 0.0),
+        ]);
+
+        assert!((rated.rating - 1464.06).abs() < 0.01, "{:?}", rated);
+        assert!((rated.deviation - 151.52).abs() < 0.01, "{:?}", rated);
+        assert!((rated.volatility - 0.05999).abs() < 0.00001, "{:?}", rated);
+    }
+
+    #[test]
+    fn idle_player_becomes_less_certain() {
+        let player = Glicko {
+            rating: 1600.0,
+            deviation: 100.0,
+            volatility: 0.06,
+        };
+        let rated = player.rate(&[]);
+
+        assert!((rated.rating - 1600.0).abs() < 1e-9);
+        assert!(rated.deviation > 100.0);
+        assert!((Glicko::default().rate(&[]).deviation - INITIAL_DEVIATION).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::models::User;
+use crate::standings::GameResult;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// A player's record in finished games
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Record {
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub ties: u32,
+    /// fraction of games won, counting ties as half a win
+    pub win_percentage: f64,
+}
+
+impl Record {
+    /// add the result of a game the player played in the given seat
+    pub fn add_result(&mut self, result: &GameResult, seat: usize) {
+        self.add_outcomes(seat_outcome(result, seat), 1);
+    }
+
+    /// add a number of games that ended with the given outcome (see `seat_outcome`)
+    pub fn add_outcomes(&mut self, outcome: &str, count: u32) {
+        self.games += count;
+        match outcome {
+            "win" => self.wins += count,
+            "loss" => self.losses += count,
+            "tie" => self.ties += count,
+            _ => (),
+        }
+        self.win_percentage = (self.wins as f64 + self.ties as f64 / 2.0) / self.games as f64;
+    }
+}
+
+/// how a finished game ended for the player in the given seat: "win", "loss", "tie", or "none" if nobody won
+pub fn seat_outcome(result: &GameResult, seat: usize) -> &'static str {
+    match result.winner {
+        _ if result.tie => "tie",
+        Some(winner) if winner == seat => "win",
+        Some(_) => "loss",
+        None => "none",
+    }
+}
+
+/// A user's position on the leaderboard
+#[derive(Serialize, Debug, Clone)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub id: i32,
+    pub username: String,
+    pub display_name: String,
+    pub rating: Option<f64>,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+/// compare two entries by rating, then by win percentage. Rated users sort before unrated users,
+/// and better entries sort first
+fn compare_entries(a: &LeaderboardEntry, b: &LeaderboardEntry) -> Ordering {
+    let rating = match (a.rating, b.rating) {
+        (Some(a), Some(b)) => b.partial_cmp(&a).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+
+    rating
+        .then_with(|| {
+            b.record
+                .win_percentage
+                .partial_cmp(&a.record.win_percentage)
+                .unwrap_or(Ordering::Equal)
+        })
+        .then_with(|| b.record.games.cmp(&a.record.games))
+        .then_with(|| a.id.cmp(&b.id))
+}
+
+/// compute the leaderboard of the given users from their records in finished games.
+/// users who played fewer than min_games games are left off
+pub fn compute_leaderboard(
+    users: Vec<User>,
+    mut records: HashMap<i32, Record>,
+    min_games: u32,
+) -> Vec<LeaderboardEntry> {
+    let mut entries = users
+        .into_iter()
+        .map(|user| LeaderboardEntry {
+            rank: 0,
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            rating: user.rating,
+            record: records.remove(&user.id).unwrap_or_default(),
+        })
+        .filter(|entry| entry.record.games >= min_games)
+        .collect::<Vec<LeaderboardEntry>>();
+
+    entries.sort_by(compare_entries);
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.rank = i + 1;
+    }
+
+    entries
+}
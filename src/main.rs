@@ -16,23 +16,41 @@ use rocket_cors::{AllowedHeaders, AllowedOrigins};
 use std::collections::HashMap;
 use std::sync::RwLock;
 
+pub mod bracket;
+pub mod event_stream;
 pub mod game;
 pub mod game_manage;
+pub mod glicko;
+pub mod leaderboard;
 pub mod models;
 pub mod pages;
+pub mod ratings;
 pub mod run_migrations;
 pub mod schema;
 pub mod shared;
+pub mod standings;
+pub mod swiss;
 pub mod users;
 
 use rocket::response::NamedFile;
 use std::path::{Path, PathBuf};
 
 pub mod gomoku;
+use game::GameRegistry;
 use gomoku::Gomoku;
 
-pub type GameType = Gomoku;
+pub const DEFAULT_GAME_TYPE: &str = "gomoku";
 pub const TOURNAMENT_GAME_PLAYERS: usize = 2;
+/// default maximum number of bots connected over websockets at once
+pub const MAX_BOTS: usize = 256;
+
+/// kinds of games hosted by the server
+pub fn game_registry() -> GameRegistry {
+    let mut kinds = GameRegistry::new();
+    kinds.register::<Gomoku>("gomoku");
+
+    kinds
+}
 
 /// routes to serve frontend
 #[get("/", rank = 9)]
@@ -53,9 +71,112 @@ fn frontend_route(file: PathBuf) -> Option<NamedFile> {
     }
 }
 
+/// rebuild all glicko ratings from the history of rated games, in rating periods of games_per_period games
+fn recompute_ratings(games_per_period: usize) {
+    let conn = run_migrations::open_db();
+    let recomputed = glicko::recompute_ratings(&conn, &game_registry(), games_per_period)
+        .expect("recomputing ratings failed");
+    for (game_id, problem) in &recomputed.skipped {
+        println!("game {}: {}", game_id, problem);
+    }
+    println!(
+        "rated {} players from {} games, skipped {} games",
+        recomputed.players,
+        recomputed.games,
+        recomputed.skipped.len()
+    );
+}
+
+/// check every game against its move log, and report any divergences
+fn check_games() {
+    let conn = run_migrations::open_db();
+    let (checked, divergences) =
+        game_manage::check_games(&conn, &game_registry()).expect("checking games failed");
+    for (game_id, problem) in &divergences {
+        println!("game {}: {}", game_id, problem);
+    }
+    println!(
+        "checked {} games, found {} divergences",
+        checked,
+        divergences.len()
+    );
+    if !divergences.is_empty() {
+        std::process::exit(1);
+    }
+}
+
 fn main() {
     // run db migrations
     run_migrations::run_migrations();
+    // run a command instead of the server if one is given
+    let usage = "usage: codekata [recompute-ratings [games_per_period] | check-games]";
+    let args = std::env::args().collect::<Vec<String>>();
+    match args.get(1).map(String::as_str) {
+        Some("recompute-ratings") => {
+            // the number of games in each rating period, which must be at least 1
+            let games_per_period = match args.get(2).map(|arg| arg.parse::<usize>()) {
+                None => glicko::GAMES_PER_PERIOD,
+                Some(Ok(games)) if games > 0 => games,
+                Some(_) => {
+                    eprintln!("games_per_period must be a positive number");
+                    eprintln!("{}", usage);
+                    std::process::exit(2);
+                }
+            };
+            return recompute_ratings(games_per_period);
+        }
+        Some("check-games") => return check_games(),
+        Some(command) => {
+            eprintln!("unknown command: {}", command);
+            eprintln!("{}", usage);
+            std::process::exit(2);
+        }
+        None => {}
+    }
+    // load running games into the cache
+    let server = game_manage::GameServer::new(game_registry());
+    let loaded = server
+        .warm_up(&run_migrations::open_db())
+        .expect("loading running games failed");
+    println!("loaded {} running games", loaded);
+    // save cached games to the db on shutdown
+    let shutdown_server = server.clone();
+    ctrlc::set_handler(move || {
+        // exit even if the games can't be saved (their moves are in the move log), so the server can always be stopped
+        match run_migrations::connect_db() {
+            Ok(conn) => {
+                let saved = shutdown_server.flush(&conn);
+                println!("saved {} cached games", saved);
+                std::process::exit(0);
+            }
+            Err(err) => {
+                eprintln!("saving cached games failed: {}", err);
+                std::process::exit(1);
+            }
+        }
+    })
+    .expect("setting shutdown handler failed");
+
+    // start app, with bots connecting over websockets on their own port
+    let rocket = app(rocket::ignite(), server.clone());
+    let pool =
+        shared::DBPool::from_rocket(&rocket).expect("connecting bots to the database failed");
+    let config = rocket.config();
+    let bot_port = config
+        .get_int("bot_port")
+        .unwrap_or_else(|_| i64::from(config.port) + 1);
+    let max_bots = config
+        .get_int("max_bots")
+        .map(|max| max as usize)
+        .unwrap_or(MAX_BOTS);
+    server
+        .listen_bots(pool, &format!("{}:{}", config.address, bot_port), max_bots)
+        .expect("listening for bots failed");
+    rocket.launch();
+}
+
+/// attach the app's fairings, state, and routes to a rocket instance
+pub fn app(rocket: rocket::Rocket, server: game_manage::GameServer) -> rocket::Rocket {
     // setup cors
     let cors = rocket_cors::CorsOptions {
         allowed_origins: AllowedOrigins::all(),
@@ -69,12 +190,29 @@ fn main() {
     }
     .to_cors()
     .unwrap();
+    // event streams each hold a worker thread, so by default they can only take half of them
+    let config = rocket.config();
+    let max_streams = config
+        .get_int("max_streams")
+        .map(|max| max as usize)
+        .unwrap_or_else(|_| usize::from(config.workers / 2));
+    server.set_max_streams(max_streams);
+    // hosts on the server's own network that webhooks can be posted to (such as bots run alongside the server)
+    let webhook_allowed_hosts = config
+        .get_slice("webhook_allowed_hosts")
+        .map(|hosts| {
+            hosts
+                .iter()
+                .filter_map(|host| host.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+    server.set_webhook_allowed_hosts(webhook_allowed_hosts);
 
-    // start app
-    rocket::ignite()
+    rocket
         .attach(cors)
         .attach(shared::DBConn::fairing())
-        .manage(RwLock::new(game_manage::GameManager::<GameType>::default()))
+        .manage(server)
         .manage(RwLock::new(HashMap::<String, users::PlayerId>::new()))
         .mount(
             "/api",
@@ -82,18 +220,38 @@ fn main() {
                 game_manage::game_get_user_authd,
                 game_manage::game_get,
                 game_manage::game_move_needed,
+                game_manage::games_awaiting_move,
+                game_manage::game_wait,
+                game_manage::game_events,
                 game_manage::game_move,
+                game_manage::game_moves,
+                game_manage::game_state_at,
                 game_manage::game_new,
                 game_manage::game_join,
                 game_manage::game_leave,
                 game_manage::game_start,
                 game_manage::game_index,
+                game_manage::game_types,
+                game_manage::tournament_new,
+                game_manage::tournament_join,
+                game_manage::tournament_leave,
+                game_manage::tournament_start,
+                game_manage::tournament_advance,
+                game_manage::tournament_get,
+                game_manage::tournament_index,
+                game_manage::tournament_standings,
+                game_manage::tournament_bracket,
+                game_manage::leaderboard,
+                game_manage::user_profile,
+                game_manage::user_games,
                 users::user_new,
                 users::user_get,
                 users::user_edit,
                 users::session_new,
                 users::session_delete,
                 users::user_generate_api_key,
+                users::user_set_webhook,
+                users::user_delete_webhook,
                 pages::page_new,
                 pages::page_get,
                 pages::page_edit,
@@ -101,5 +259,4 @@ fn main() {
         )
         .mount("/", routes![frontend_route, frontend_root])
         .register(catchers![users::unauthorized])
-        .launch();
 }
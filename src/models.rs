@@ -1,18 +1,30 @@
 use crate::schema::db_games;
+use crate::schema::game_moves;
+use crate::schema::game_players;
 use crate::schema::pages;
+use crate::schema::rating_history;
 use crate::schema::tournaments;
 use crate::schema::users;
 use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
 
 #[derive(Queryable, Debug)]
 pub struct DbGame {
     pub id: i32,
     pub title: String,
-    pub state: Option<String>,
+    pub state: Option<serde_json::Value>,
     pub owner_id: i32,
-    pub players: String,
-    pub active: i32,
     pub is_public: bool,
+    pub game_type: String,
+    pub tournament_id: Option<i32>,
+    pub rated: bool,
+    pub moves: i32,
+    pub status: String,
+    pub created_at: SystemTime,
+    pub started_at: Option<SystemTime>,
+    pub finished_at: Option<SystemTime>,
+    pub last_move_at: Option<SystemTime>,
+    pub version: i32,
 }
 
 #[derive(Insertable, AsChangeset)]
@@ -20,22 +32,68 @@ pub struct DbGame {
 pub struct InsertDbGame<'a> {
     pub id: i32,
     pub title: &'a str,
-    pub state: Option<String>,
+    pub state: Option<serde_json::Value>,
     pub owner_id: i32,
-    pub players: String,
-    pub active: i32,
     pub is_public: bool,
+    pub game_type: &'a str,
+    pub tournament_id: Option<i32>,
+    pub rated: bool,
+    pub moves: i32,
+    pub status: &'a str,
+    pub started_at: Option<SystemTime>,
+    pub finished_at: Option<SystemTime>,
+    pub last_move_at: Option<SystemTime>,
+    pub version: i32,
 }
 
 #[derive(Insertable)]
 #[table_name = "db_games"]
 pub struct NewDbGame<'a> {
     pub title: &'a str,
-    pub state: Option<String>,
+    pub state: Option<serde_json::Value>,
     pub owner_id: i32,
-    pub players: String,
-    pub active: i32,
     pub is_public: bool,
+    pub game_type: &'a str,
+    pub tournament_id: Option<i32>,
+    pub rated: bool,
+    pub moves: i32,
+    pub status: &'a str,
+    pub started_at: Option<SystemTime>,
+}
+
+/// A move made in a game. ply is the number of the move (starting at 1)
+#[derive(Queryable, Debug)]
+pub struct DbGameMove {
+    pub game_id: i32,
+    pub ply: i32,
+    pub seat: i32,
+    pub player_move: String,
+    pub created_at: SystemTime,
+    pub think_time_ms: Option<i64>,
+    /// key sent with the move, so retries of it can be recognized
+    pub idempotency_key: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "game_moves"]
+pub struct NewDbGameMove<'a> {
+    pub game_id: i32,
+    pub ply: i32,
+    pub seat: i32,
+    pub player_move: &'a str,
+    pub think_time_ms: Option<i64>,
+    pub idempotency_key: Option<&'a str>,
+}
+
+/// A player's seat in a game
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "game_players"]
+pub struct DbGamePlayer {
+    pub game_id: i32,
+    pub user_id: i32,
+    pub seat: i32,
+    /// how the game ended for the player, once it is finished (see `leaderboard::seat_outcome`)
+    pub outcome: Option<String>,
 }
 
 #[derive(Queryable, Insertable, AsChangeset, Clone)]
@@ -47,6 +105,13 @@ pub struct User {
     pub password_hash: String,
     pub api_key_hash: Option<String>,
     pub is_admin: bool,
+    pub rating: Option<f64>,
+    pub glicko_rating: Option<f64>,
+    pub glicko_deviation: Option<f64>,
+    pub glicko_volatility: Option<f64>,
+    /// url the user's turns are posted to, and the secret the posts are signed with
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -59,6 +124,25 @@ pub struct NewUser<'a> {
     pub is_admin: bool,
 }
 
+#[derive(Queryable, Debug)]
+pub struct RatingHistory {
+    pub id: i32,
+    pub user_id: i32,
+    pub game_id: i32,
+    pub rating_before: f64,
+    pub rating_after: f64,
+    pub created_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "rating_history"]
+pub struct NewRatingHistory {
+    pub user_id: i32,
+    pub game_id: i32,
+    pub rating_before: f64,
+    pub rating_after: f64,
+}
+
 #[derive(Queryable, Insertable, AsChangeset, Clone, Debug)]
 #[table_name = "tournaments"]
 pub struct Tournament {
@@ -67,6 +151,13 @@ pub struct Tournament {
     pub players: Vec<i32>,
     pub games: Option<Vec<i32>>,
     pub owner_id: i32,
+    pub game_type: String,
+    pub format: String,
+    pub current_round: i32,
+    pub rounds: Option<i32>,
+    pub byes: Vec<i32>,
+    pub best_of: i32,
+    pub bracket: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -76,6 +167,10 @@ pub struct NewTournament<'a> {
     pub players: Vec<i32>,
     pub games: Option<Vec<i32>>,
     pub owner_id: i32,
+    pub game_type: &'a str,
+    pub format: &'a str,
+    pub rounds: Option<i32>,
+    pub best_of: i32,
 }
 
 #[derive(Queryable, Insertable, AsChangeset, Clone, Debug, FromForm, Serialize)]
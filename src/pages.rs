@@ -2,7 +2,7 @@ use rocket::request::{Form, FromRequest, FromSegments, Outcome};
 use rocket_contrib::json::Json;
 
 use crate::models::{NewPage, NewUser, Page, User};
-use crate::shared::{DBConn, Error, ErrorResp, IdResp, SuccessResp};
+use crate::shared::{DBConn, Error, IdResp, SuccessResp};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -26,15 +26,11 @@ pub struct NewPageForm {
 }
 
 #[post("/pages/new", data = "<page>")]
-pub fn page_new(
-    page: Form<NewPageForm>,
-    db: DBConn,
-    user: User,
-) -> Result<Json<IdResp>, Json<ErrorResp>> {
+pub fn page_new(page: Form<NewPageForm>, db: DBConn, user: User) -> Result<Json<IdResp>, Error> {
     use crate::schema::pages;
 
     if !user.is_admin {
-        Err(Json::from(Error::NotAdmin))
+        Err(Error::NotAdmin)
     } else {
         let new_entry = NewPage {
             url: &page.url,
@@ -43,8 +39,7 @@ pub fn page_new(
 
         let inserted = diesel::insert_into(pages::table)
             .values(&new_entry)
-            .get_result::<Page>(&*db)
-            .map_err(|e| Error::from(e))?;
+            .get_result::<Page>(&*db)?;
 
         Ok(Json(IdResp {
             id: inserted.id.to_string(),
@@ -53,20 +48,15 @@ pub fn page_new(
 }
 
 #[post("/pages/edit", data = "<page>")]
-pub fn page_edit(
-    page: Form<Page>,
-    db: DBConn,
-    user: User,
-) -> Result<Json<SuccessResp>, Json<ErrorResp>> {
+pub fn page_edit(page: Form<Page>, db: DBConn, user: User) -> Result<Json<SuccessResp>, Error> {
     use crate::schema::pages;
 
     if !user.is_admin {
-        Err(Json::from(Error::NotAdmin))
+        Err(Error::NotAdmin)
     } else {
         diesel::update(pages::dsl::pages.find(page.id))
             .set(&*page)
-            .execute(&*db)
-            .map_err(|e| Error::from(e))?;
+            .execute(&*db)?;
 
         Ok(Json(SuccessResp { success: true }))
     }
@@ -90,13 +80,14 @@ impl<'a> FromSegments<'a> for PageUrl {
 }
 
 #[get("/pages/<path..>")]
-pub fn page_get(path: PageUrl, db: DBConn) -> Result<Json<Page>, Json<ErrorResp>> {
+pub fn page_get(path: PageUrl, db: DBConn) -> Result<Json<Page>, Error> {
     use crate::schema::pages;
 
     let page = pages::dsl::pages
         .filter(pages::dsl::url.eq(path.0))
         .first::<Page>(&*db)
-        .map_err(|e| Error::from(e))?;
+        .optional()?
+        .ok_or(Error::NoSuchPage)?;
 
     Ok(Json(page))
 }
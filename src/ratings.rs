@@ -0,0 +1,121 @@
+use crate::models::NewRatingHistory;
+use crate::shared::Error;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+/// rating given to players before their first rated game
+pub const INITIAL_RATING: f64 = 1500.0;
+/// maximum rating change from a single game
+const K_FACTOR: f64 = 32.0;
+
+/// expected score (between 0 and 1) of a player with the given rating against an opponent
+pub fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
+
+/// new elo ratings of two players after a game between them.
+/// score is the first player's score (1 for a win, 0.5 for a tie, 0 for a loss)
+pub fn elo_update(first: f64, second: f64, score: f64) -> (f64, f64) {
+    let change = K_FACTOR * (score - expected_score(first, second));
+
+    (first + change, second - change)
+}
+
+/// update the ratings of two players after a rated game, and record the change in their rating history.
+/// runs in the transaction the finished game is saved in, so the game and its ratings are saved together
+pub fn apply_elo(
+    conn: &PgConnection,
+    game_id: i32,
+    players: [i32; 2],
+    score: f64,
+) -> Result<(), Error> {
+    use crate::schema::rating_history;
+    use crate::schema::users;
+
+    conn.transaction::<_, Error, _>(|| {
+        // rows are locked in order of user id, so games between the same players finishing at once can't deadlock
+        let order = if players[0] <= players[1] {
+            [0, 1]
+        } else {
+            [1, 0]
+        };
+        let mut ratings = [INITIAL_RATING; 2];
+        for &i in &order {
+            ratings[i] = users::dsl::users
+                .find(players[i])
+                .select(users::dsl::rating)
+                .for_update()
+                .first::<Option<f64>>(conn)?
+                .unwrap_or(INITIAL_RATING);
+        }
+
+        let (first, second) = elo_update(ratings[0], ratings[1], score);
+        for &(player, before, after) in &[
+            (players[0], ratings[0], first),
+            (players[1], ratings[1], second),
+        ] {
+            diesel::update(users::dsl::users.find(player))
+                .set(users::dsl::rating.eq(after))
+                .execute(conn)?;
+            diesel::insert_into(rating_history::table)
+                .values(&NewRatingHistory {
+                    user_id: player,
+                    game_id,
+                    rating_before: before,
+                    rating_after: after,
+                })
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn equal_ratings_expect_even_score() {
+        assert_close(expected_score(1500.0, 1500.0), 0.5);
+        assert_close(expected_score(1900.0, 1500.0), 10.0 / 11.0);
+        assert_close(
+            expected_score(1700.0, 1500.0) + expected_score(1500.0, 1700.0),
+            1.0,
+        );
+    }
+
+    #[test]
+    fn win_between_equal_players_moves_half_k() {
+        assert_eq!(elo_update(1500.0, 1500.0, 1.0), (1516.0, 1484.0));
+        assert_eq!(elo_update(1500.0, 1500.0, 0.0), (1484.0, 1516.0));
+        assert_eq!(elo_update(1500.0, 1500.0, 0.5), (1500.0, 1500.0));
+    }
+
+    #[test]
+    fn upset_moves_more_than_expected_win() {
+        let (favourite, underdog) = elo_update(1900.0, 1500.0, 1.0);
+        assert_close(favourite, 1900.0 + 32.0 / 11.0);
+        assert_close(underdog, 1500.0 - 32.0 / 11.0);
+
+        let (favourite, underdog) = elo_update(1900.0, 1500.0, 0.0);
+        assert_close(favourite, 1900.0 - 320.0 / 11.0);
+        assert_close(underdog, 1500.0 + 320.0 / 11.0);
+    }
+
+    #[test]
+    fn tie_pulls_ratings_together_and_keeps_total() {
+        let (high, low) = elo_update(1800.0, 1600.0, 0.5);
+        assert!(high < 1800.0 && low > 1600.0);
+        assert_close(high + low, 3400.0);
+    }
+}
@@ -3,13 +3,21 @@ use diesel::prelude::*;
 use dotenv::dotenv;
 use std::env;
 
-fn open_db() -> PgConnection {
+pub fn open_db() -> PgConnection {
     dotenv().ok();
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url))
 }
 
+/// connect to the database, returning an error instead of panicking if it can't be reached
+pub fn connect_db() -> ConnectionResult<PgConnection> {
+    dotenv().ok();
+
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    PgConnection::establish(&database_url)
+}
+
 pub fn run_migrations() {
     let conn = open_db();
     diesel_migrations::run_pending_migrations(&conn).expect("running migrations failed");
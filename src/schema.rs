@@ -2,11 +2,40 @@ table! {
     db_games (id) {
         id -> Int4,
         title -> Varchar,
-        state -> Nullable<Text>,
+        state -> Nullable<Jsonb>,
         owner_id -> Int4,
-        players -> Varchar,
-        active -> Int4,
         is_public -> Bool,
+        game_type -> Varchar,
+        tournament_id -> Nullable<Int4>,
+        rated -> Bool,
+        moves -> Int4,
+        status -> Varchar,
+        created_at -> Timestamp,
+        started_at -> Nullable<Timestamp>,
+        finished_at -> Nullable<Timestamp>,
+        last_move_at -> Nullable<Timestamp>,
+        version -> Int4,
+    }
+}
+
+table! {
+    game_moves (game_id, ply) {
+        game_id -> Int4,
+        ply -> Int4,
+        seat -> Int4,
+        player_move -> Text,
+        created_at -> Timestamp,
+        think_time_ms -> Nullable<Int8>,
+        idempotency_key -> Nullable<Text>,
+    }
+}
+
+table! {
+    game_players (game_id, seat) {
+        game_id -> Int4,
+        user_id -> Int4,
+        seat -> Int4,
+        outcome -> Nullable<Varchar>,
     }
 }
 
@@ -18,6 +47,17 @@ table! {
     }
 }
 
+table! {
+    rating_history (id) {
+        id -> Int4,
+        user_id -> Int4,
+        game_id -> Int4,
+        rating_before -> Float8,
+        rating_after -> Float8,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     tournaments (id) {
         id -> Int4,
@@ -25,6 +65,13 @@ table! {
         players -> Array<Int4>,
         games -> Nullable<Array<Int4>>,
         owner_id -> Int4,
+        game_type -> Varchar,
+        format -> Varchar,
+        current_round -> Int4,
+        rounds -> Nullable<Int4>,
+        byes -> Array<Int4>,
+        best_of -> Int4,
+        bracket -> Nullable<Text>,
     }
 }
 
@@ -36,7 +83,27 @@ table! {
         password_hash -> Text,
         api_key_hash -> Nullable<Text>,
         is_admin -> Bool,
+        rating -> Nullable<Float8>,
+        glicko_rating -> Nullable<Float8>,
+        glicko_deviation -> Nullable<Float8>,
+        glicko_volatility -> Nullable<Float8>,
+        webhook_url -> Nullable<Text>,
+        webhook_secret -> Nullable<Text>,
     }
 }
 
-allow_tables_to_appear_in_same_query!(db_games, pages, tournaments, users,);
+joinable!(game_moves -> db_games (game_id));
+joinable!(game_players -> db_games (game_id));
+joinable!(game_players -> users (user_id));
+joinable!(rating_history -> db_games (game_id));
+joinable!(rating_history -> users (user_id));
+
+allow_tables_to_appear_in_same_query!(
+    db_games,
+    game_moves,
+    game_players,
+    pages,
+    rating_history,
+    tournaments,
+    users,
+);
@@ -1,9 +1,112 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::{Outcome, Rocket};
+use rocket_contrib::databases::{database_config, r2d2, Poolable};
 use rocket_contrib::json::Json;
 use serde::Serialize;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[database("db")]
 pub struct DBConn(diesel::PgConnection);
 
+/// A request guard that gets database connections when they are needed, instead of holding one for the whole request
+pub struct LazyDBConn<'a, 'r>(&'a Request<'r>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for LazyDBConn<'a, 'r> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(LazyDBConn(request))
+    }
+}
+
+/// A handle to the database pool, for getting connections outside of requests
+#[derive(Clone)]
+pub struct DBPool(r2d2::Pool<<diesel::PgConnection as Poolable>::Manager>);
+
+impl DBPool {
+    /// connect a new pool to the database configured for a rocket instance, the same way rocket connects its pool
+    pub fn from_rocket(rocket: &Rocket) -> Result<DBPool, String> {
+        let config = database_config("db", rocket.config()).map_err(|err| err.to_string())?;
+        diesel::PgConnection::pool(config)
+            .map(DBPool)
+            .map_err(|err| err.to_string())
+    }
+
+    /// get a connection from the pool
+    pub fn get(&self) -> Result<DBConn, Error> {
+        self.0.get().map(DBConn).map_err(|_| Error::GuardLoadError)
+    }
+}
+
+impl<'a, 'r> LazyDBConn<'a, 'r> {
+    /// get a connection from the pool
+    pub fn get(&self) -> Result<DBConn, Error> {
+        self.0
+            .guard::<DBConn>()
+            .succeeded()
+            .ok_or(Error::GuardLoadError)
+    }
+}
+
+/// A request guard for the address of the client making a request (from the X-Real-IP header, if the server is behind a proxy)
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(ClientIp(request.client_ip()))
+    }
+}
+
+/// A request guard for the entity tags in a request's If-None-Match header
+pub struct IfNoneMatch(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfNoneMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(IfNoneMatch(
+            request.headers().get_one("If-None-Match").map(String::from),
+        ))
+    }
+}
+
+impl IfNoneMatch {
+    /// check if the client already has the version with the given tag (weak tags match their strong versions)
+    pub fn matches(&self, etag: &str) -> bool {
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        self.0.as_ref().map_or(false, |header| {
+            header
+                .split(',')
+                .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+        })
+    }
+}
+
+/// A response tagged with the version of what it describes, or an empty 304 (Not Modified) response if the client already has that version
+pub enum Tagged<R> {
+    Modified(String, R),
+    NotModified(String),
+}
+
+impl<'r, R: Responder<'r>> Responder<'r> for Tagged<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            Tagged::Modified(etag, body) => Response::build_from(body.respond_to(request)?)
+                .raw_header("ETag", etag)
+                .ok(),
+            Tagged::NotModified(etag) => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag)
+                .ok(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     InvalidGameId,
@@ -27,6 +130,130 @@ pub enum Error {
     WrongTurn,
     InvalidMove,
     NotAdmin,
+    InvalidGameType,
+    InvalidTournamentId,
+    InvalidTournamentFormat,
+    InvalidNumRounds,
+    InvalidSeriesLength,
+    NoBracket,
+    InvalidPage,
+    InvalidUserId,
+    InvalidGameStatus,
+    InvalidPly,
+    MissingMoveLog,
+    ReplayFailed,
+    GameChanged,
+    InvalidTimeout,
+    NoApiKey,
+    InvalidWebhookUrl,
+    StalePly,
+    IdempotencyKeyReused,
+    TooManyWaits,
+    TooManyStreams,
+    NoSuchPage,
+}
+
+impl Error {
+    /// the http status an error is sent with
+    pub fn status(&self) -> Status {
+        match self {
+            Error::InvalidGameId
+            | Error::NoSuchUser
+            | Error::InvalidTournamentId
+            | Error::NoBracket
+            | Error::InvalidUserId
+            | Error::InvalidPly
+            | Error::MissingMoveLog
+            | Error::NoSuchPage => Status::NotFound,
+            Error::DBError(_)
+            | Error::SerializeError(_)
+            | Error::HashError(_)
+            | Error::GuardLoadError
+            | Error::InvalidGameStatus
+            | Error::ReplayFailed => Status::InternalServerError,
+            Error::MalformedApiKey
+            | Error::InvalidPassword
+            | Error::Unauthorized
+            | Error::NoAuthorizationMethod
+            | Error::InvalidApiKey => Status::Unauthorized,
+            Error::UsernameAlreadyTaken
+            | Error::GameAlreadyStarted
+            | Error::AlreadyInGame
+            | Error::InvalidNumPlayers
+            | Error::GameNotStarted
+            | Error::WrongTurn
+            | Error::GameChanged
+            | Error::NoApiKey
+            | Error::StalePly => Status::Conflict,
+            Error::NotGameOwner | Error::NotJoinedGame | Error::NotAdmin => Status::Forbidden,
+            Error::InvalidMove
+            | Error::InvalidGameType
+            | Error::InvalidTournamentFormat
+            | Error::InvalidNumRounds
+            | Error::InvalidSeriesLength
+            | Error::InvalidWebhookUrl
+            | Error::IdempotencyKeyReused => Status::UnprocessableEntity,
+            Error::InvalidPage | Error::InvalidTimeout => Status::BadRequest,
+            Error::TooManyWaits | Error::TooManyStreams => Status::TooManyRequests,
+        }
+    }
+
+    /// a stable name for an error, for clients to check instead of its message
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidGameId => "invalid_game_id",
+            Error::DBError(_) => "db_error",
+            Error::SerializeError(_) => "serialize_error",
+            Error::MalformedApiKey => "malformed_api_key",
+            Error::UsernameAlreadyTaken => "username_already_taken",
+            Error::HashError(_) => "hash_error",
+            Error::NoSuchUser => "no_such_user",
+            Error::InvalidPassword => "invalid_password",
+            Error::Unauthorized => "unauthorized",
+            Error::NoAuthorizationMethod => "no_authorization_method",
+            Error::InvalidApiKey => "invalid_api_key",
+            Error::GuardLoadError => "guard_load_error",
+            Error::GameAlreadyStarted => "game_already_started",
+            Error::AlreadyInGame => "already_in_game",
+            Error::InvalidNumPlayers => "invalid_num_players",
+            Error::NotGameOwner => "not_game_owner",
+            Error::NotJoinedGame => "not_joined_game",
+            Error::GameNotStarted => "game_not_started",
+            Error::WrongTurn => "wrong_turn",
+            Error::InvalidMove => "invalid_move",
+            Error::NotAdmin => "not_admin",
+            Error::InvalidGameType => "invalid_game_type",
+            Error::InvalidTournamentId => "invalid_tournament_id",
+            Error::InvalidTournamentFormat => "invalid_tournament_format",
+            Error::InvalidNumRounds => "invalid_num_rounds",
+            Error::InvalidSeriesLength => "invalid_series_length",
+            Error::NoBracket => "no_bracket",
+            Error::InvalidPage => "invalid_page",
+            Error::InvalidUserId => "invalid_user_id",
+            Error::InvalidGameStatus => "invalid_game_status",
+            Error::InvalidPly => "invalid_ply",
+            Error::MissingMoveLog => "missing_move_log",
+            Error::ReplayFailed => "replay_failed",
+            Error::GameChanged => "game_changed",
+            Error::InvalidTimeout => "invalid_timeout",
+            Error::NoApiKey => "no_api_key",
+            Error::InvalidWebhookUrl => "invalid_webhook_url",
+            Error::StalePly => "stale_ply",
+            Error::IdempotencyKeyReused => "idempotency_key_reused",
+            Error::TooManyWaits => "too_many_waits",
+            Error::TooManyStreams => "too_many_streams",
+            Error::NoSuchPage => "no_such_page",
+        }
+    }
+}
+
+impl<'r> Responder<'r> for Error {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let status = self.status();
+        Response::build_from(Json(ErrorResp::from(self)).respond_to(request)?)
+            .status(status)
+            .ok()
+    }
 }
 
 impl From<serde_json::Error> for Error {
@@ -50,12 +277,14 @@ impl From<diesel::result::Error> for Error {
 #[derive(Serialize, Debug)]
 pub struct ErrorResp {
     pub error: String,
+    pub code: &'static str,
     pub success: bool,
 }
 
 impl From<Error> for ErrorResp {
     fn from(err: Error) -> ErrorResp {
         ErrorResp {
+            code: err.code(),
             error: match err {
                 Error::DBError(e) => format!("database error: {}", e.to_string()),
                 Error::SerializeError(e) => format!("data serialization error: {}", e.to_string()),
@@ -78,18 +307,41 @@ impl From<Error> for ErrorResp {
                 Error::WrongTurn => "player played out of turn".to_string(),
                 Error::InvalidMove => "invalid move".to_string(),
                 Error::NotAdmin => "player does not have admin authorization".to_string(),
+                Error::InvalidGameType => "invalid game type".to_string(),
+                Error::InvalidTournamentId => "invalid tournament id".to_string(),
+                Error::InvalidTournamentFormat => "invalid tournament format".to_string(),
+                Error::InvalidNumRounds => "invalid number of rounds".to_string(),
+                Error::InvalidSeriesLength => {
+                    "series length must be a positive odd number".to_string()
+                }
+                Error::NoBracket => "tournament does not have a bracket".to_string(),
+                Error::InvalidPage => "invalid page".to_string(),
+                Error::InvalidUserId => "invalid user id".to_string(),
+                Error::InvalidGameStatus => "invalid game status".to_string(),
+                Error::InvalidPly => "invalid ply".to_string(),
+                Error::MissingMoveLog => "game does not have a complete move log".to_string(),
+                Error::ReplayFailed => "game's moves could not be replayed".to_string(),
+                Error::GameChanged => {
+                    "game was changed by another request, reload it and try again".to_string()
+                }
+                Error::InvalidTimeout => "invalid timeout".to_string(),
+                Error::NoApiKey => "generate an api key first".to_string(),
+                Error::InvalidWebhookUrl => {
+                    "webhook url must be http or https, with a public host".to_string()
+                }
+                Error::StalePly => "move is not for the game's next ply".to_string(),
+                Error::IdempotencyKeyReused => {
+                    "idempotency key was already used for a different move".to_string()
+                }
+                Error::TooManyWaits => "too many requests waiting at once".to_string(),
+                Error::TooManyStreams => "too many event streams open at once".to_string(),
+                Error::NoSuchPage => "no such page".to_string(),
             },
             success: false,
         }
     }
 }
 
-impl From<Error> for rocket_contrib::json::Json<ErrorResp> {
-    fn from(e: Error) -> Self {
-        Json(ErrorResp::from(e))
-    }
-}
-
 #[derive(Serialize, Debug)]
 pub struct IdResp {
     pub id: String,
@@ -99,3 +351,9 @@ pub struct IdResp {
 pub struct SuccessResp {
     pub success: bool,
 }
+
+/// seconds since the unix epoch of a time, for sending to clients
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
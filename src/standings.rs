@@ -0,0 +1,274 @@
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// scores closer together than this are considered equal
+const SCORE_EPSILON: f64 = 1e-9;
+/// score given to a player for sitting out a round (the score of a win)
+const BYE_SCORE: f64 = 1.0;
+
+/// The result of a finished game, used to compute standings
+pub struct GameResult {
+    /// players in the game, in seat order
+    pub players: Vec<i32>,
+    /// score for each seat
+    pub scores: Vec<f64>,
+    /// seat of the winner, or None if the game was tied or had no winner
+    pub winner: Option<usize>,
+    /// if the game was tied
+    pub tie: bool,
+}
+
+/// A player's position in the standings
+#[derive(Serialize, Debug, Clone)]
+pub struct Standing {
+    pub rank: usize,
+    pub player_id: i32,
+    pub score: f64,
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub ties: u32,
+    /// score in games against players with the same total score
+    pub head_to_head: f64,
+    /// sum over all opponents of the opponent's total score times the score made against them
+    pub sonneborn_berger: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Standings {
+    /// standings ordered by rank
+    pub standings: Vec<Standing>,
+    /// `crosstable[i][j]` is the total score of player i against player j (indexed in the order players were given),
+    /// or None if they didn't play
+    pub crosstable: Vec<Vec<Option<f64>>>,
+}
+
+fn scores_equal(a: f64, b: f64) -> bool {
+    (a - b).abs() < SCORE_EPSILON
+}
+
+fn compare_scores(a: f64, b: f64) -> Ordering {
+    if scores_equal(a, b) {
+        Ordering::Equal
+    } else {
+        a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// compare two standings by score, then by tiebreakers (head-to-head, Sonneborn-Berger, wins). Better standings sort first
+fn compare_standings(a: &Standing, b: &Standing) -> Ordering {
+    compare_scores(b.score, a.score)
+        .then_with(|| compare_scores(b.head_to_head, a.head_to_head))
+        .then_with(|| compare_scores(b.sonneborn_berger, a.sonneborn_berger))
+        .then_with(|| b.wins.cmp(&a.wins))
+}
+
+/// compute the standings and crosstable for the given players from the results of their games.
+/// byes lists the players given a bye, once for each bye
+pub fn compute_standings(players: &[i32], results: &[GameResult], byes: &[i32]) -> Standings {
+    let index = players
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, i))
+        .collect::<HashMap<i32, usize>>();
+
+    let mut standings = players
+        .iter()
+        .map(|id| Standing {
+            rank: 0,
+            player_id: *id,
+            score: 0.0,
+            games: 0,
+            wins: 0,
+            losses: 0,
+            ties: 0,
+            head_to_head: 0.0,
+            sonneborn_berger: 0.0,
+        })
+        .collect::<Vec<Standing>>();
+    let mut crosstable = vec![vec![None; players.len()]; players.len()];
+
+    for result in results {
+        for (seat, player) in result.players.iter().enumerate() {
+            let i = match index.get(player) {
+                Some(i) => *i,
+                None => continue,
+            };
+            let score = result.scores.get(seat).cloned().unwrap_or(0.0);
+
+            let standing = &mut standings[i];
+            standing.score += score;
+            standing.games += 1;
+            if result.tie {
+                standing.ties += 1;
+            } else if let Some(winner) = result.winner {
+                if winner == seat {
+                    standing.wins += 1;
+                } else {
+                    standing.losses += 1;
+                }
+            }
+
+            for opponent in result.players.iter().filter(|id| *id != player) {
+                if let Some(j) = index.get(opponent) {
+                    let cell: &mut Option<f64> = &mut crosstable[i][*j];
+                    *cell = Some(cell.unwrap_or(0.0) + score);
+                }
+            }
+        }
+    }
+
+    for player in byes {
+        if let Some(i) = index.get(player) {
+            standings[*i].score += BYE_SCORE;
+        }
+    }
+
+    let totals = standings.iter().map(|s| s.score).collect::<Vec<f64>>();
+    for (i, standing) in standings.iter_mut().enumerate() {
+        for (j, cell) in crosstable[i].iter().enumerate() {
+            if let Some(score) = cell {
+                standing.sonneborn_berger += score * totals[j];
+                if scores_equal(totals[i], totals[j]) {
+                    standing.head_to_head += score;
+                }
+            }
+        }
+    }
+
+    standings.sort_by(compare_standings);
+    for i in 0..standings.len() {
+        standings[i].rank =
+            if i > 0 && compare_standings(&standings[i - 1], &standings[i]) == Ordering::Equal {
+                standings[i - 1].rank
+            } else {
+                i + 1
+            };
+    }
+
+    Standings {
+        standings,
+        crosstable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn win(winner: i32, loser: i32) -> GameResult {
+        GameResult {
+            players: vec![winner, loser],
+            scores: vec![1.0, 0.0],
+            winner: Some(0),
+            tie: false,
+        }
+    }
+
+    fn tie(first: i32, second: i32) -> GameResult {
+        GameResult {
+            players: vec![first, second],
+            scores: vec![0.5, 0.5],
+            winner: None,
+            tie: true,
+        }
+    }
+
+    fn ranks(standings: &Standings) -> Vec<(i32, usize)> {
+        standings
+            .standings
+            .iter()
+            .map(|s| (s.player_id, s.rank))
+            .collect()
+    }
+
+    #[test]
+    fn bye_scores_as_win_and_head_to_head_breaks_tie() {
+        let standings = compute_standings(&[1, 2], &[win(2, 1)], &[1]);
+
+        assert_eq!(ranks(&standings), vec![(2, 1), (1, 2)]);
+        let first = &standings.standings[0];
+        assert_eq!(
+            (first.score, first.head_to_head, first.wins, first.games),
+            (1.0, 1.0, 1, 1)
+        );
+        let second = &standings.standings[1];
+        assert_eq!(
+            (
+                second.score,
+                second.head_to_head,
+                second.losses,
+                second.games
+            ),
+            (1.0, 0.0, 1, 1)
+        );
+
+        assert_eq!(
+            standings.crosstable,
+            vec![vec![None, Some(0.0)], vec![Some(1.0), None]]
+        );
+    }
+
+    #[test]
+    fn sonneborn_berger_breaks_tie_between_players_who_didnt_meet() {
+        // 1 and 2 both have one win, but 1 beat the stronger opponent
+        let results = [win(1, 3), win(2, 4), win(3, 5), win(3, 6)];
+        let standings = compute_standings(&[1, 2, 3, 4, 5, 6], &results, &[]);
+
+        assert_eq!(
+            ranks(&standings)[..3].to_vec(),
+            vec![(3, 1), (1, 2), (2, 3)]
+        );
+        let sonneborn_berger = standings
+            .standings
+            .iter()
+            .map(|s| s.sonneborn_berger)
+            .collect::<Vec<f64>>();
+        assert_eq!(sonneborn_berger[1..3].to_vec(), vec![2.0, 0.0]);
+    }
+
+    #[test]
+    fn wins_break_tie_when_other_tiebreaks_equal() {
+        // 1 and 2 both score 1 against opponents who also scored 1, but 1 has a win and a loss while 2 has two ties
+        let results = [
+            win(1, 3),
+            win(4, 1),
+            tie(2, 5),
+            tie(2, 6),
+            win(3, 7),
+            tie(5, 8),
+            tie(6, 8),
+        ];
+        let standings = compute_standings(&[1, 2, 3, 4, 5, 6, 7, 8], &results, &[]);
+
+        let one = standings
+            .standings
+            .iter()
+            .find(|s| s.player_id == 1)
+            .unwrap();
+        let two = standings
+            .standings
+            .iter()
+            .find(|s| s.player_id == 2)
+            .unwrap();
+        assert_eq!(
+            (one.score, one.head_to_head, one.sonneborn_berger),
+            (1.0, 1.0, 1.0)
+        );
+        assert_eq!(
+            (two.score, two.head_to_head, two.sonneborn_berger),
+            (1.0, 1.0, 1.0)
+        );
+        assert!(one.rank < two.rank);
+    }
+
+    #[test]
+    fn identical_standings_share_rank() {
+        let standings = compute_standings(&[1, 2, 3], &[tie(1, 2)], &[]);
+
+        assert_eq!(ranks(&standings), vec![(1, 1), (2, 1), (3, 3)]);
+        assert_eq!(standings.standings[0].ties, 1);
+        assert_eq!(standings.standings[1].ties, 1);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::{HashMap, HashSet};
+
+/// maximum number of pairings to try while avoiding rematches before allowing them
+const MAX_PAIRING_STEPS: usize = 100_000;
+
+/// A player's situation going into a swiss round
+pub struct SwissPlayer {
+    pub id: i32,
+    /// total score so far
+    pub score: f64,
+    /// number of games played in the first seat minus number of games played in the second seat
+    pub first_balance: i32,
+    /// if the player has already received a bye
+    pub had_bye: bool,
+}
+
+/// The pairings for one swiss round
+pub struct SwissRound {
+    /// pairs of players, in seat order
+    pub pairs: Vec<(i32, i32)>,
+    /// player sitting out the round, if there is an odd number of players
+    pub bye: Option<i32>,
+}
+
+/// Pair players for the next swiss round.
+/// `players` should be in seeding order, and `played` contains every pair of players that have already played.
+/// Players are paired with others on the same score where possible, rematches are avoided if possible,
+/// and the player who has moved first less often moves first.
+pub fn pair_round(players: &[SwissPlayer], played: &HashSet<(i32, i32)>) -> SwissRound {
+    // rank by score, keeping seeding order for equal scores
+    let mut ranked = players.iter().collect::<Vec<&SwissPlayer>>();
+    ranked.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    // the lowest ranked player who hasn't had a bye sits out
+    let bye = if ranked.len() % 2 == 1 {
+        let pos = ranked
+            .iter()
+            .rposition(|p| !p.had_bye)
+            .unwrap_or(ranked.len() - 1);
+        Some(ranked.remove(pos).id)
+    } else {
+        None
+    };
+
+    let ids = ranked.iter().map(|p| p.id).collect::<Vec<i32>>();
+    let have_played =
+        |a: i32, b: i32| -> bool { played.contains(&(a, b)) || played.contains(&(b, a)) };
+
+    let mut steps = 0;
+    let matched = match pair_without_rematches(&ids, &mut vec![], &have_played, &mut steps) {
+        Some(pairs) => pairs,
+        // no pairing avoids rematches, so pair by rank
+        None => ids.chunks(2).map(|pair| (pair[0], pair[1])).collect(),
+    };
+
+    let balances = players
+        .iter()
+        .map(|p| (p.id, p.first_balance))
+        .collect::<HashMap<i32, i32>>();
+    let pairs = matched
+        .into_iter()
+        .map(|(higher, lower)| {
+            // the higher ranked player moves first unless they have moved first more often
+            if balances[&lower] < balances[&higher] {
+                (lower, higher)
+            } else {
+                (higher, lower)
+            }
+        })
+        .collect();
+
+    SwissRound { pairs, bye }
+}
+
+/// pair the unpaired players in rank order, backtracking to avoid rematches
+fn pair_without_rematches(
+    unpaired: &[i32],
+    pairs: &mut Vec<(i32, i32)>,
+    have_played: &dyn Fn(i32, i32) -> bool,
+    steps: &mut usize,
+) -> Option<Vec<(i32, i32)>> {
+    if unpaired.is_empty() {
+        return Some(pairs.clone());
+    }
+
+    let top = unpaired[0];
+    for i in 1..unpaired.len() {
+        *steps += 1;
+        if *steps > MAX_PAIRING_STEPS {
+            return None;
+        }
+
+        let opponent = unpaired[i];
+        if have_played(top, opponent) {
+            continue;
+        }
+
+        let rest = unpaired[1..]
+            .iter()
+            .filter(|id| **id != opponent)
+            .cloned()
+            .collect::<Vec<i32>>();
+        pairs.push((top, opponent));
+        if let Some(res) = pair_without_rematches(&rest, pairs, have_played, steps) {
+            return Some(res);
+        }
+        pairs.pop();
+    }
+
+    None
+}
+
+/// default number of swiss rounds for the number of players (enough to find a clear winner)
+pub fn default_rounds(num_players: usize) -> i32 {
+    let mut rounds = 0;
+    while (1usize << rounds) < num_players {
+        rounds += 1;
+    }
+
+    rounds.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(id: i32, score: f64) -> SwissPlayer {
+        SwissPlayer {
+            id,
+            score,
+            first_balance: 0,
+            had_bye: false,
+        }
+    }
+
+    #[test]
+    fn pairs_by_score_keeping_seed_order() {
+        let players = [
+            player(1, 0.0),
+            player(2, 1.0),
+            player(3, 0.0),
+            player(4, 1.0),
+        ];
+        let round = pair_round(&players, &HashSet::new());
+
+        assert_eq!(round.pairs, vec![(2, 4), (1, 3)]);
+        assert_eq!(round.bye, None);
+    }
+
+    #[test]
+    fn player_who_moved_first_more_often_moves_second() {
+        let mut players = [player(1, 0.0), player(2, 0.0)];
+        players[0].first_balance = 1;
+        let round = pair_round(&players, &HashSet::new());
+
+        assert_eq!(round.pairs, vec![(2, 1)]);
+    }
+
+    #[test]
+    fn bye_goes_to_lowest_ranked_player_without_one() {
+        let mut players = [
+            player(1, 2.0),
+            player(2, 1.0),
+            player(3, 0.0),
+            player(4, 1.0),
+            player(5, 0.0),
+        ];
+        let round = pair_round(&players, &HashSet::new());
+        assert_eq!(round.bye, Some(5));
+        assert_eq!(round.pairs, vec![(1, 2), (4, 3)]);
+
+        players[4].had_bye = true;
+        let round = pair_round(&players, &HashSet::new());
+        assert_eq!(round.bye, Some(3));
+        assert_eq!(round.pairs, vec![(1, 2), (4, 5)]);
+    }
+
+    #[test]
+    fn backtracks_to_avoid_rematches() {
+        let players = [
+            player(1, 0.0),
+            player(2, 0.0),
+            player(3, 0.0),
+            player(4, 0.0),
+        ];
+        let played = [(4, 3)].iter().cloned().collect();
+        let round = pair_round(&players, &played);
+
+        assert_eq!(round.pairs, vec![(1, 3), (2, 4)]);
+    }
+
+    #[test]
+    fn falls_back_to_rank_order_when_search_is_too_long() {
+        // the last player has played everyone, so every pairing is a rematch,
+        // and there are far too many pairings of the rest to try them all
+        let players = (1..=20).map(|id| player(id, 0.0)).collect::<Vec<_>>();
+        let played = (1..20).map(|id| (id, 20)).collect();
+        let round = pair_round(&players, &played);
+
+        let expected = (1..=10)
+            .map(|i| (2 * i - 1, 2 * i))
+            .collect::<Vec<(i32, i32)>>();
+        assert_eq!(round.pairs, expected);
+    }
+
+    #[test]
+    fn default_rounds_grows_with_log_of_players() {
+        let rounds = [1, 2, 5, 8, 9]
+            .iter()
+            .map(|n| default_rounds(*n))
+            .collect::<Vec<i32>>();
+        assert_eq!(rounds, vec![1, 1, 3, 3, 4]);
+    }
+}
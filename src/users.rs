@@ -1,8 +1,10 @@
 use rocket::request::{Form, FromRequest, Outcome};
 use rocket_contrib::json::Json;
 
+use crate::game_manage::AppReqState;
+use crate::glicko::Glicko;
 use crate::models::{NewUser, User};
-use crate::shared::{DBConn, Error, ErrorResp, SuccessResp};
+use crate::shared::{DBConn, Error, SuccessResp};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -204,12 +206,17 @@ impl<'a> UserManager<'a> {
         }
     }
 
-    /// save a user to the db
+    /// save a user's profile (username, display name, and password) to the db
+    /// (ratings are only changed when games finish, so they aren't saved here)
     pub fn save_user(&self, user: &User) -> Result<(), Error> {
         use crate::schema::users;
 
         diesel::update(users::dsl::users.find(user.id))
-            .set(user)
+            .set((
+                users::dsl::username.eq(&user.username),
+                users::dsl::display_name.eq(&user.display_name),
+                users::dsl::password_hash.eq(&user.password_hash),
+            ))
             .execute(&*self.db)?;
         Ok(())
     }
@@ -236,12 +243,47 @@ impl<'a> UserManager<'a> {
         let key = ApiKey::new();
         let hash = key.hash();
 
+        // webhooks belong to the api key they were registered with
         diesel::update(users::dsl::users.find(user_id.0))
-            .set(users::dsl::api_key_hash.eq(hash.to_string()))
+            .set((
+                users::dsl::api_key_hash.eq(hash.to_string()),
+                users::dsl::webhook_url.eq(None::<String>),
+                users::dsl::webhook_secret.eq(None::<String>),
+            ))
             .execute(&*self.db)?;
         Ok(key.to_string())
     }
 
+    /// register a url for a user's turns to be posted to, and return the secret the posts are signed with
+    pub fn set_webhook(&self, user: &User, url: &str) -> Result<String, Error> {
+        use crate::schema::users;
+
+        if user.api_key_hash.is_none() {
+            return Err(Error::NoApiKey);
+        }
+        let secret = format!("{}", Uuid::new_v4().simple());
+        diesel::update(users::dsl::users.find(user.id))
+            .set((
+                users::dsl::webhook_url.eq(url),
+                users::dsl::webhook_secret.eq(&secret),
+            ))
+            .execute(&*self.db)?;
+        Ok(secret)
+    }
+
+    /// stop posting a user's turns to their webhook
+    pub fn delete_webhook(&self, user_id: PlayerId) -> Result<(), Error> {
+        use crate::schema::users;
+
+        diesel::update(users::dsl::users.find(user_id.0))
+            .set((
+                users::dsl::webhook_url.eq(None::<String>),
+                users::dsl::webhook_secret.eq(None::<String>),
+            ))
+            .execute(&*self.db)?;
+        Ok(())
+    }
+
     /// create a new session for the given user
     pub fn new_session(&self, user_id: PlayerId) -> String {
         let mut sessions = self.sessions.write().unwrap();
@@ -359,7 +401,7 @@ pub fn session_new(
     db: DBConn,
     state: UserManagerState,
     mut cookies: Cookies,
-) -> Result<Json<SuccessResp>, Json<ErrorResp>> {
+) -> Result<Json<SuccessResp>, Error> {
     let manage = UserManager::new(db, &*state);
     let user = manage.find_user(&login.username)?;
 
@@ -371,7 +413,7 @@ pub fn session_new(
 
         Ok(Json(SuccessResp { success: true }))
     } else {
-        Err(Json::from(Error::InvalidPassword))
+        Err(Error::InvalidPassword)
     }
 }
 
@@ -380,7 +422,7 @@ pub fn session_delete(
     db: DBConn,
     state: UserManagerState,
     mut cookies: Cookies,
-) -> Result<Json<SuccessResp>, Json<ErrorResp>> {
+) -> Result<Json<SuccessResp>, Error> {
     let manage = UserManager::new(db, &*state);
     if let Some(session) = cookies.get_private("session_key") {
         manage.end_session(session.value());
@@ -388,7 +430,7 @@ pub fn session_delete(
 
         Ok(Json(SuccessResp { success: true }))
     } else {
-        Err(Json(ErrorResp::from(Error::NoAuthorizationMethod)))
+        Err(Error::NoAuthorizationMethod)
     }
 }
 
@@ -404,7 +446,7 @@ pub fn user_new(
     user: Form<NewUserForm>,
     db: DBConn,
     state: UserManagerState,
-) -> Result<Json<SuccessResp>, Json<ErrorResp>> {
+) -> Result<Json<SuccessResp>, Error> {
     UserManager::new(db, &*state).new_user(
         &*user.username,
         &*user.display_name,
@@ -419,8 +461,11 @@ pub struct UserResp {
     username: String,
     display_name: String,
     has_api_key: bool,
+    webhook_url: Option<String>,
     id: i32,
     is_admin: bool,
+    rating: Option<f64>,
+    glicko: Option<Glicko>,
 }
 
 #[get("/user")]
@@ -429,8 +474,15 @@ pub fn user_get(user: User) -> Json<UserResp> {
         username: user.username,
         display_name: user.display_name,
         has_api_key: user.api_key_hash.is_some(),
+        webhook_url: user.webhook_url,
         id: user.id,
         is_admin: user.is_admin,
+        rating: user.rating,
+        glicko: Glicko::from_columns(
+            user.glicko_rating,
+            user.glicko_deviation,
+            user.glicko_volatility,
+        ),
     })
 }
 
@@ -447,14 +499,14 @@ pub fn user_edit(
     db: DBConn,
     state: UserManagerState,
     mut user: User,
-) -> Result<Json<SuccessResp>, Json<ErrorResp>> {
+) -> Result<Json<SuccessResp>, Error> {
     let manage = UserManager::new(db, &*state);
 
     if let Some(username) = &edit.username {
         if *username != user.username {
             // check that username isn't already taken
             if manage.find_user(&*username).is_ok() {
-                return Err(Json(ErrorResp::from(Error::UsernameAlreadyTaken)));
+                return Err(Error::UsernameAlreadyTaken);
             }
         }
         user.username = username.clone();
@@ -463,7 +515,7 @@ pub fn user_edit(
         user.display_name = display_name.clone();
     };
     if let Some(password) = &edit.password {
-        user.password_hash = bcrypt::hash(password, BCRYPT_COST).map_err(Error::from)?;
+        user.password_hash = bcrypt::hash(password, BCRYPT_COST)?;
     };
 
     manage.save_user(&user)?;
@@ -474,6 +526,8 @@ pub fn user_edit(
 #[derive(Serialize)]
 pub struct ApiKeyResponse {
     key: String,
+    /// if the user's webhook was removed along with their old api key
+    webhook_removed: bool,
 }
 
 #[post("/user/generate_api")]
@@ -481,13 +535,51 @@ pub fn user_generate_api_key(
     user: User,
     db: DBConn,
     state: UserManagerState,
-) -> Result<Json<ApiKeyResponse>, Json<ErrorResp>> {
+) -> Result<Json<ApiKeyResponse>, Error> {
     let manage = UserManager::new(db, &*state);
     let key = manage.generate_api_key(PlayerId::new(user.id))?;
-    Ok(Json(ApiKeyResponse { key }))
+    Ok(Json(ApiKeyResponse {
+        key,
+        webhook_removed: user.webhook_url.is_some(),
+    }))
+}
+
+#[derive(FromForm)]
+pub struct WebhookForm {
+    url: String,
+}
+
+#[derive(Serialize)]
+pub struct WebhookResponse {
+    secret: String,
+}
+
+#[post("/user/webhook", data = "<webhook>")]
+pub fn user_set_webhook(
+    webhook: Form<WebhookForm>,
+    user: User,
+    db: DBConn,
+    state: UserManagerState,
+    server: AppReqState,
+) -> Result<Json<WebhookResponse>, Error> {
+    server.check_webhook_url(&webhook.url)?;
+    let manage = UserManager::new(db, &*state);
+    let secret = manage.set_webhook(&user, &webhook.url)?;
+    Ok(Json(WebhookResponse { secret }))
+}
+
+#[post("/user/webhook/delete")]
+pub fn user_delete_webhook(
+    user: User,
+    db: DBConn,
+    state: UserManagerState,
+) -> Result<Json<SuccessResp>, Error> {
+    let manage = UserManager::new(db, &*state);
+    manage.delete_webhook(PlayerId::new(user.id))?;
+    Ok(Json(SuccessResp { success: true }))
 }
 
 #[catch(401)]
-pub fn unauthorized(_: &Request) -> Json<ErrorResp> {
-    Json(ErrorResp::from(Error::Unauthorized))
+pub fn unauthorized(_: &Request) -> Error {
+    Error::Unauthorized
 }